@@ -0,0 +1,84 @@
+//! Throughput benchmark for `csv_read`.
+//!
+//! Generates a synthetic workload for each size, then reports rows/sec
+//! and the peak heap usage while processing it. Sizes default to 1M and
+//! 10M rows and can be overridden with `BENCH_ROWS=100000,500000`.
+//!
+//! `cargo bench --bench csv_read`
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env,
+    fs::{self, File},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+use rust_coding_test::{
+    csv_read,
+    workload::{generate, WorkloadConfig},
+};
+
+/// Allocator wrapper keeping track of the current and peak heap usage.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc;
+
+fn sizes() -> Vec<u64> {
+    match env::var("BENCH_ROWS") {
+        Ok(rows) => rows
+            .split(',')
+            .map(|n| n.trim().parse().expect("BENCH_ROWS must be a list of integers"))
+            .collect(),
+        Err(_) => vec![1_000_000, 10_000_000],
+    }
+}
+
+fn main() {
+    for rows in sizes() {
+        let path = env::temp_dir().join(format!("csv_read_bench_{}.csv", rows));
+        let config = WorkloadConfig {
+            clients: u16::MAX,
+            transactions: rows,
+            ..WorkloadConfig::default()
+        };
+        generate(&config, File::create(&path).unwrap()).unwrap();
+
+        let baseline = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+        let start = Instant::now();
+        let accounts = csv_read(path.to_str().unwrap(), true).unwrap();
+        let elapsed = start.elapsed();
+        let peak = PEAK.load(Ordering::Relaxed) - baseline;
+
+        println!(
+            "csv_read/{:>10} rows: {:>8.2?} {:>12.0} rows/sec, peak heap {:>8.1} MiB, {} accounts",
+            rows,
+            elapsed,
+            rows as f64 / elapsed.as_secs_f64(),
+            peak as f64 / (1024.0 * 1024.0),
+            accounts.len()
+        );
+        drop(accounts);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+//! Async front-end of the engine, enabled by the `async` feature.
+//!
+//! `EngineHandle` owns the engine in an actor task: any number of producers
+//! submit rows and queries as messages without blocking. `CsvStream` reads
+//! CSV transactions from an async reader, one line at a time.
+use rust_csv::{ReaderBuilder, StringRecord, Trim};
+use tokio::{io::{AsyncBufRead, AsyncBufReadExt, Lines}, sync::{mpsc, oneshot}, task::JoinHandle};
+use crate::{audit::Rejection, AccountSnapshot, Engine, Errors, Input, Mode};
+
+/// Pending messages before producers wait for the engine.
+const CAPACITY: usize = 1024;
+
+enum Message {
+    Apply { row: Input, line: i32, reply: oneshot::Sender<Result<(), Errors>> },
+    Account { client: u16, asset: String, reply: oneshot::Sender<Option<AccountSnapshot>> },
+    Accounts { reply: oneshot::Sender<Vec<AccountSnapshot>> },
+    DrainRejections { reply: oneshot::Sender<Vec<Rejection>> },
+}
+
+/// Cloneable handle to an engine owned by an actor task.
+#[derive(Clone)]
+pub struct EngineHandle {
+    sender: mpsc::Sender<Message>,
+}
+
+impl EngineHandle {
+    /// Spawn the actor task owning `engine` on the current tokio runtime.
+    ///
+    /// The task stops once every handle is dropped, returning the engine.
+    pub fn spawn(mut engine: Engine) -> (EngineHandle, JoinHandle<Engine>) {
+        let (sender, mut receiver) = mpsc::channel(CAPACITY);
+        let task = tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                match message {
+                    Message::Apply { row, line, reply } => {
+                        let _ = reply.send(engine.apply(row, line));
+                    }
+                    Message::Account { client, asset, reply } => {
+                        let key = (client, asset);
+                        let _ = reply.send(engine.account(client, &key.1).map(|acc| AccountSnapshot::new(&key, acc)));
+                    }
+                    Message::Accounts { reply } => {
+                        let _ = reply.send(engine.account_snapshots());
+                    }
+                    Message::DrainRejections { reply } => {
+                        let _ = reply.send(engine.drain_rejections());
+                    }
+                }
+            }
+            engine
+        });
+        (EngineHandle { sender }, task)
+    }
+
+    /// Send a message and wait for its reply, `None` when the engine stopped.
+    async fn request<T>(&self, message: impl FnOnce(oneshot::Sender<T>) -> Message) -> Option<T> {
+        let (reply, response) = oneshot::channel();
+        self.sender.send(message(reply)).await.ok()?;
+        response.await.ok()
+    }
+
+    /// Apply a single row, see `Engine::apply`.
+    pub async fn apply(&self, row: Input, line: i32) -> Result<(), Errors> {
+        self.request(|reply| Message::Apply { row, line, reply }).await
+            .unwrap_or_else(|| Err(Errors::InvalidInput("engine stopped".to_string())))
+    }
+
+    pub async fn account(&self, client: u16, asset: &str) -> Option<AccountSnapshot> {
+        let asset = asset.to_string();
+        self.request(|reply| Message::Account { client, asset, reply }).await.flatten()
+    }
+
+    pub async fn accounts(&self) -> Vec<AccountSnapshot> {
+        self.request(|reply| Message::Accounts { reply }).await.unwrap_or_default()
+    }
+
+    pub async fn drain_rejections(&self) -> Vec<Rejection> {
+        self.request(|reply| Message::DrainRejections { reply }).await.unwrap_or_default()
+    }
+}
+
+/// CSV transactions read from an async reader.
+///
+/// Rows are read line by line, so quoted fields cannot span several lines.
+/// Like `process_csv`, lines are numbered from the first row after the headers.
+pub struct CsvStream<R> {
+    lines: Lines<R>,
+    headers: Option<StringRecord>,
+    line: i32,
+}
+
+/// Parse a single CSV line, accepting whitespaces and missing trailing columns.
+fn parse_record(content: &str) -> Option<StringRecord> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).flexible(true).has_headers(false).from_reader(content.as_bytes());
+    let mut record = StringRecord::new();
+    match reader.read_record(&mut record) {
+        Ok(true) => Some(record),
+        _ => None,
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> CsvStream<R> {
+    pub fn new(reader: R) -> Self {
+        CsvStream { lines: reader.lines(), headers: None, line: 0 }
+    }
+
+    /// Next row and its line number, `None` at the end of the stream.
+    pub async fn next_row(&mut self) -> Option<(i32, Result<Input, Errors>)> {
+        loop {
+            let content = match self.lines.next_line().await {
+                Ok(Some(content)) => content,
+                Ok(None) => return None,
+                Err(e) => {
+                    self.line += 1;
+                    return Some((self.line, Err(Errors::InvalidInput(e.to_string()))));
+                }
+            };
+            if content.trim().is_empty() {
+                continue;
+            }
+            let headers = match &self.headers {
+                Some(headers) => headers,
+                None => {
+                    self.headers = parse_record(&content);
+                    continue;
+                }
+            };
+            self.line += 1;
+            let row = parse_record(&content)
+                .and_then(|record| record.deserialize::<Input>(Some(headers)).ok())
+                .ok_or_else(|| Errors::InvalidRecord(self.line.to_string()));
+            return Some((self.line, row));
+        }
+    }
+}
+
+/// Read CSV transactions from `reader` and submit them to the engine.
+///
+/// Errors are handled like `process_csv`: returned at once in strict mode,
+/// collected in lenient mode.
+pub async fn process_stream<R: AsyncBufRead + Unpin>(handle: &EngineHandle, reader: R, mode: Mode) -> Result<Vec<Errors>, Errors> {
+    let mut stream = CsvStream::new(reader);
+    let mut errors = Vec::new();
+    while let Some((line, row)) = stream.next_row().await {
+        let applied = match row {
+            Ok(row) => handle.apply(row, line).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = applied {
+            match mode {
+                Mode::Strict => return Err(error),
+                Mode::Lenient => errors.push(error),
+            }
+        }
+    }
+    Ok(errors)
+}
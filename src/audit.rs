@@ -0,0 +1,141 @@
+use core::fmt;
+use serde::{Serialize, Serializer};
+use serde_derive::Serialize;
+use crate::{rules::RuleAction, Operation};
+
+/// Audit entries recorded by the engine alongside balance changes.
+///
+/// Serialized as one JSON object per entry, tagged by `kind`.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEntry {
+    /// Fee charged on a transaction and credited to the house account.
+    Fee {
+        line: i32,
+        tx: u32,
+        client: u16,
+        asset: String,
+        operation: Operation,
+        amount: f32,
+        house_account: u16,
+    },
+    /// Transaction rejected or held by a rule. Allowed transactions are not recorded.
+    Rule {
+        line: i32,
+        tx: u32,
+        client: u16,
+        rule: String,
+        action: RuleAction,
+        reason: String,
+    },
+    /// Account locked by an auto lock trigger.
+    Lock {
+        line: i32,
+        tx: u32,
+        client: u16,
+        asset: String,
+        reason: String,
+    },
+}
+
+impl AuditEntry {
+    /// Amount moved by the entry.
+    pub fn amount(&self) -> f32 {
+        match self {
+            AuditEntry::Fee { amount, .. } => *amount,
+            AuditEntry::Rule { .. } | AuditEntry::Lock { .. } => 0.0,
+        }
+    }
+
+    /// Cap the amount of the entry, e.g. a fee cannot exceed the deposit.
+    pub(crate) fn capped(mut self, max: f32) -> Self {
+        if let AuditEntry::Fee { amount, .. } = &mut self {
+            *amount = amount.min(max);
+        }
+        self
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Reason of a rejected transaction, serialized by its `name`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum RejectReason {
+    WithdrawalAboveMax,
+    DailyLimitExceeded,
+    WindowAmountExceeded,
+    WindowCountExceeded,
+    RuleRejected,
+    DisputeWindowExpired,
+    DisputableAmountExceeded,
+    DisputedAmountExceeded,
+    DuplicateTransaction,
+    FailedTransaction,
+}
+
+impl RejectReason {
+    /// Name of the reason in snake case, also used to serialize it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RejectReason::WithdrawalAboveMax => "withdrawal_above_max",
+            RejectReason::DailyLimitExceeded => "daily_limit_exceeded",
+            RejectReason::WindowAmountExceeded => "window_amount_exceeded",
+            RejectReason::WindowCountExceeded => "window_count_exceeded",
+            RejectReason::RuleRejected => "rule_rejected",
+            RejectReason::DisputeWindowExpired => "dispute_window_expired",
+            RejectReason::DisputableAmountExceeded => "disputable_amount_exceeded",
+            RejectReason::DisputedAmountExceeded => "disputed_amount_exceeded",
+            RejectReason::DuplicateTransaction => "duplicate_transaction",
+            RejectReason::FailedTransaction => "failed_transaction",
+        }
+    }
+}
+
+impl Serialize for RejectReason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit_variant("RejectReason", *self as u32, self.name())
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RejectReason::WithdrawalAboveMax => write!(f, "Withdrawal above maximum amount"),
+            RejectReason::DailyLimitExceeded => write!(f, "Daily withdrawal limit exceeded"),
+            RejectReason::WindowAmountExceeded => write!(f, "Withdrawal window amount exceeded"),
+            RejectReason::WindowCountExceeded => write!(f, "Withdrawal window count exceeded"),
+            RejectReason::RuleRejected => write!(f, "Rejected by rule"),
+            RejectReason::DisputeWindowExpired => write!(f, "Dispute window expired"),
+            RejectReason::DisputableAmountExceeded => write!(f, "Amount above the disputable amount"),
+            RejectReason::DisputedAmountExceeded => write!(f, "Amount above the disputed amount"),
+            RejectReason::DuplicateTransaction => write!(f, "Duplicate transaction"),
+            RejectReason::FailedTransaction => write!(f, "Transaction was not applied"),
+        }
+    }
+}
+
+/// Transaction rejected by the engine. Unlike `Errors`, rejections
+/// never stop the processing: the transaction is skipped and reported.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Rejection {
+    pub line: i32,
+    pub tx: u32,
+    pub client: u16,
+    pub reason: RejectReason,
+}
+
+impl Rejection {
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} for tx {} at line: {}", self.reason, self.tx, self.line)
+    }
+}
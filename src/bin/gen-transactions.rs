@@ -0,0 +1,75 @@
+use std::{env, fs::File, io, process};
+use rust_coding_test::workload::{generate, WorkloadConfig};
+
+const USAGE: &str = "Generate a synthetic CSV transaction workload.
+
+Usage: gen-transactions [OPTIONS]
+
+Options:
+  --clients <N>            Number of distinct clients [default: 1000]
+  --transactions <N>       Number of rows to generate [default: 100000]
+  --dispute-ratio <F>      Probability of a row being a dispute [default: 0.01]
+  --chargeback-ratio <F>   Share of disputes settled by chargeback [default: 0.2]
+  --malformed-ratio <F>    Probability of a row being malformed [default: 0.0]
+  --seed <N>               Random seed [default: 42]
+  --output <PATH>          Output file [default: stdout]
+  -h, --help               Print this help";
+
+fn parse<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {}", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+fn parse_ratio(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let ratio: f64 = parse(flag, value)?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(format!("{} must be between 0.0 and 1.0", flag));
+    }
+    Ok(ratio)
+}
+
+fn parse_args() -> Result<(WorkloadConfig, Option<String>), String> {
+    let mut config = WorkloadConfig::default();
+    let mut output = None;
+    let mut arguments = env::args().skip(1);
+    while let Some(flag) = arguments.next() {
+        match flag.as_str() {
+            "--clients" => config.clients = parse(&flag, arguments.next())?,
+            "--transactions" => config.transactions = parse(&flag, arguments.next())?,
+            "--dispute-ratio" => config.dispute_ratio = parse_ratio(&flag, arguments.next())?,
+            "--chargeback-ratio" => config.chargeback_ratio = parse_ratio(&flag, arguments.next())?,
+            "--malformed-ratio" => config.malformed_ratio = parse_ratio(&flag, arguments.next())?,
+            "--seed" => config.seed = parse(&flag, arguments.next())?,
+            "--output" => output = Some(parse(&flag, arguments.next())?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument: {}", flag)),
+        }
+    }
+    if config.clients == 0 {
+        return Err("--clients must be greater than 0".to_string());
+    }
+    Ok((config, output))
+}
+
+fn main() {
+    let (config, output) = match parse_args() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let written = match output {
+        Some(path) => File::create(&path).and_then(|f| generate(&config, f)),
+        None => generate(&config, io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        eprintln!("Unable to write workload: {}", e);
+        process::exit(1);
+    }
+}
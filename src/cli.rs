@@ -0,0 +1,427 @@
+use rust_coding_test::{reconcile::Tolerance, replay::AsOf, Mode};
+
+pub const USAGE: &str = "Toy payments engine: process CSV transactions and output client accounts.
+
+Usage: rust_coding_test [process] [OPTIONS] <FILE>...
+       rust_coding_test reconcile --expected <PATH> [OPTIONS] <FILE>...
+       rust_coding_test simulate --state <PATH> [OPTIONS] --batch <FILE>...
+       rust_coding_test balances --as-of-line <N> [OPTIONS] <FILE>...
+
+Commands:
+  process    Process transaction files in order and output the resulting accounts
+  reconcile  Process transaction files and compare the resulting accounts
+             against an expected accounts CSV, outputting the differences
+  simulate   Apply transaction files to a saved state without changing it,
+             outputting the changed accounts and the rejected transactions
+  balances   Output the accounts as of a line, tx or timestamp of the input
+
+Arguments:
+  <FILE>...  CSV files, directories or file name patterns (`*`, `?`).
+             Directories and patterns expand to their files sorted by name.
+
+Options:
+  -o, --output <PATH>        Write accounts to PATH instead of stdout
+  -f, --format <FORMAT>      Output format: csv, json [default: csv]
+      --status               Add the account status (active, frozen, closed)
+                             and the reason, line and tx of frozen accounts
+  -m, --mode <MODE>          strict: stop at the first invalid row
+                             lenient: skip invalid rows and keep processing [default: strict]
+  -e, --errors-file <PATH>   Write errors to PATH instead of stderr
+      --merge                Interleave files by their `timestamp` column instead
+                             of processing them one after the other
+  -c, --config <PATH>        JSON engine configuration (fee schedule, ...)
+      --audit-file <PATH>    Write audit entries (fees, ...) to PATH as JSON lines
+      --rejections-file <PATH>
+                             Write rejected transactions (withdrawal limits, ...)
+                             to PATH as JSON lines
+      --held-file <PATH>     Write transactions held for review by a rule to PATH
+                             as JSON lines, instead of a warning on stderr
+      --save-state <PATH>    Save the engine state to PATH as JSON, for `simulate`
+                             (not supported by `simulate` itself)
+      --history <PATH>       Write the balances of each account changed by an
+                             applied transaction to PATH
+      --history-format <FORMAT>
+                             Balance history format: csv, ndjson [default: csv]
+      --history-clients <IDS>
+                             Only write the history of these clients, e.g. 1,2,5
+      --summary              Print a summary of the run to stderr: rows by operation,
+                             accepted and rejected rows by reason, amounts
+                             deposited, withdrawn, held and charged back,
+                             locked accounts and processing rate
+      --summary-file <PATH>  Write the summary of the run to PATH as JSON
+  -h, --help                 Print this help
+
+Reconcile options:
+      --expected <PATH>      Expected accounts CSV, with the columns of the csv output
+      --tolerance <AMOUNT>   Accepted absolute difference of amounts [default: 0]
+      --relative-tolerance <PERCENT>
+                             Accepted difference of amounts, in percent of the
+                             expected amount [default: 0]
+
+Simulate options:
+      --state <PATH>         Engine state saved with `--save-state`
+      --batch <FILE>         Transaction file to simulate, same as <FILE>
+                             Rejections are written to stderr unless
+                             --rejections-file or --format json is used
+
+Balances options:
+      --as-of-line <N>       Accounts after the first N rows of the input,
+                             counted across files in processing order
+      --as-of-tx <TX>        Accounts after the first row with tx TX
+      --as-of-timestamp <TIMESTAMP>
+                             Accounts after the rows up to TIMESTAMP
+                             Reports, history, summary and state cover the rows
+                             up to that point
+
+Exit codes:
+  0  Success
+  1  Input error (unreadable file, invalid row)
+  2  Usage error
+  3  Critical security error (conflicting transaction, divergent client ID)
+  4  Accounts differ from the expected accounts (reconcile)";
+
+/// Output format of the accounts.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/// Format of the balance history.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HistoryFormat {
+    Csv,
+    Ndjson,
+}
+
+/// Arguments of the `process` command.
+#[derive(Debug, PartialEq)]
+pub struct ProcessArgs {
+    pub files: Vec<String>,
+    pub output: Option<String>,
+    pub format: Format,
+    pub status: bool,
+    pub mode: Mode,
+    pub errors_file: Option<String>,
+    pub merge: bool,
+    pub config: Option<String>,
+    pub audit_file: Option<String>,
+    pub rejections_file: Option<String>,
+    pub held_file: Option<String>,
+    pub save_state: Option<String>,
+    pub history: Option<String>,
+    pub history_format: HistoryFormat,
+    /// Clients of the balance history, all clients when `None`.
+    pub history_clients: Option<Vec<u16>>,
+    pub summary: bool,
+    pub summary_file: Option<String>,
+}
+
+/// Arguments of the `reconcile` command.
+#[derive(Debug, PartialEq)]
+pub struct ReconcileArgs {
+    pub process: ProcessArgs,
+    pub expected: String,
+    pub tolerance: Tolerance,
+}
+
+/// Arguments of the `simulate` command.
+#[derive(Debug, PartialEq)]
+pub struct SimulateArgs {
+    pub process: ProcessArgs,
+    pub state: String,
+}
+
+/// Arguments of the `balances` command.
+#[derive(Debug, PartialEq)]
+pub struct BalancesArgs {
+    pub process: ProcessArgs,
+    pub as_of: AsOf,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Process(ProcessArgs),
+    Reconcile(ReconcileArgs),
+    Simulate(SimulateArgs),
+    Balances(BalancesArgs),
+    Help,
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+fn tolerance(flag: &str, value: Option<String>) -> Result<f32, String> {
+    let value = self::value(flag, value)?;
+    match value.parse::<f32>() {
+        Ok(tolerance) if tolerance >= 0.0 => Ok(tolerance),
+        _ => Err(format!("Invalid tolerance: {}", value)),
+    }
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = self::value(flag, value)?;
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+/// Parse the arguments following the binary name.
+///
+/// `process` is the default command, so `rust_coding_test transactions.csv`
+/// keeps working as described in the challenge. The other commands accept
+/// the options of `process` besides their own.
+pub fn parse<I: Iterator<Item = String>>(arguments: I) -> Result<Command, String> {
+    let mut arguments = arguments.peekable();
+    let command = match arguments.peek().map(String::as_str) {
+        Some(command @ ("process" | "reconcile" | "simulate" | "balances")) => {
+            let command = command.to_string();
+            arguments.next();
+            command
+        }
+        _ => "process".to_string(),
+    };
+    let (reconcile, simulate, balances) = (command == "reconcile", command == "simulate", command == "balances");
+    let mut expected = None;
+    let mut as_of = None;
+    let mut state = None;
+    let mut tolerances = Tolerance::default();
+    let mut args = ProcessArgs {
+        files: Vec::new(),
+        output: None,
+        format: Format::Csv,
+        status: false,
+        mode: Mode::Strict,
+        errors_file: None,
+        merge: false,
+        config: None,
+        audit_file: None,
+        rejections_file: None,
+        held_file: None,
+        save_state: None,
+        history: None,
+        history_format: HistoryFormat::Csv,
+        history_clients: None,
+        summary: false,
+        summary_file: None,
+    };
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--merge" => args.merge = true,
+            "--status" => args.status = true,
+            "--summary" => args.summary = true,
+            "--summary-file" => args.summary_file = Some(value(&argument, arguments.next())?),
+            "-c" | "--config" => args.config = Some(value(&argument, arguments.next())?),
+            "--audit-file" => args.audit_file = Some(value(&argument, arguments.next())?),
+            "--rejections-file" => args.rejections_file = Some(value(&argument, arguments.next())?),
+            "--held-file" => args.held_file = Some(value(&argument, arguments.next())?),
+            "--expected" if reconcile => expected = Some(value(&argument, arguments.next())?),
+            "--tolerance" if reconcile => tolerances.absolute = tolerance(&argument, arguments.next())?,
+            "--relative-tolerance" if reconcile => tolerances.relative = tolerance(&argument, arguments.next())?,
+            "--state" if simulate => state = Some(value(&argument, arguments.next())?),
+            "--batch" if simulate => args.files.push(value(&argument, arguments.next())?),
+            "--as-of-line" if balances => as_of = Some(AsOf::Line(number(&argument, arguments.next())?)),
+            "--as-of-tx" if balances => as_of = Some(AsOf::Tx(number(&argument, arguments.next())?)),
+            "--as-of-timestamp" if balances => as_of = Some(AsOf::Timestamp(number(&argument, arguments.next())?)),
+            "--history" => args.history = Some(value(&argument, arguments.next())?),
+            "--history-format" => {
+                args.history_format = match value(&argument, arguments.next())?.as_str() {
+                    "csv" => HistoryFormat::Csv,
+                    "ndjson" => HistoryFormat::Ndjson,
+                    other => return Err(format!("Invalid history format: {}", other)),
+                }
+            }
+            "--history-clients" => {
+                let clients = value(&argument, arguments.next())?;
+                let clients = clients.split(',').map(|client| client.trim().parse().map_err(|_| format!("Invalid client: {}", client)));
+                args.history_clients = Some(clients.collect::<Result<Vec<u16>, String>>()?);
+            }
+            "--save-state" if simulate => return Err(format!("{} is not supported by simulate", argument)),
+            "--save-state" => args.save_state = Some(value(&argument, arguments.next())?),
+            "-o" | "--output" => args.output = Some(value(&argument, arguments.next())?),
+            "-e" | "--errors-file" => args.errors_file = Some(value(&argument, arguments.next())?),
+            "-f" | "--format" => {
+                args.format = match value(&argument, arguments.next())?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    other => return Err(format!("Invalid format: {}", other)),
+                }
+            }
+            "-m" | "--mode" => {
+                args.mode = match value(&argument, arguments.next())?.as_str() {
+                    "strict" => Mode::Strict,
+                    "lenient" => Mode::Lenient,
+                    other => return Err(format!("Invalid mode: {}", other)),
+                }
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("Unknown option: {}", flag))
+            }
+            _ => args.files.push(argument),
+        }
+    }
+    if args.files.is_empty() {
+        return Err("Missing input file".to_string());
+    }
+    if reconcile {
+        let expected = expected.ok_or_else(|| "Missing expected accounts file (--expected)".to_string())?;
+        return Ok(Command::Reconcile(ReconcileArgs { process: args, expected, tolerance: tolerances }));
+    }
+    if simulate {
+        let state = state.ok_or_else(|| "Missing engine state (--state)".to_string())?;
+        return Ok(Command::Simulate(SimulateArgs { process: args, state }));
+    }
+    if balances {
+        let as_of = as_of.ok_or_else(|| "Missing point in time (--as-of-line, --as-of-tx or --as-of-timestamp)".to_string())?;
+        return Ok(Command::Balances(BalancesArgs { process: args, as_of }));
+    }
+    Ok(Command::Process(args))
+}
+
+/// A bare file argument defaults to the `process` command with strict CSV output,
+/// while options and several files are accepted after `process`.
+#[test]
+fn parse_process_arguments() {
+    let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>().into_iter();
+
+    let expected = ProcessArgs {
+        files: vec!["transactions.csv".to_string()],
+        output: None,
+        format: Format::Csv,
+        status: false,
+        mode: Mode::Strict,
+        errors_file: None,
+        merge: false,
+        config: None,
+        audit_file: None,
+        rejections_file: None,
+        held_file: None,
+        save_state: None,
+        history: None,
+        history_format: HistoryFormat::Csv,
+        history_clients: None,
+        summary: false,
+        summary_file: None,
+    };
+    assert_eq!(parse(args(&["transactions.csv"])), Ok(Command::Process(expected)));
+
+    let expected = ProcessArgs {
+        files: vec!["day1.csv".to_string(), "day2.csv".to_string()],
+        output: Some("accounts.json".to_string()),
+        format: Format::Json,
+        status: true,
+        mode: Mode::Lenient,
+        errors_file: Some("errors.txt".to_string()),
+        merge: true,
+        config: Some("engine.json".to_string()),
+        audit_file: None,
+        rejections_file: None,
+        held_file: Some("held.jsonl".to_string()),
+        save_state: Some("state.json".to_string()),
+        history: Some("history.ndjson".to_string()),
+        history_format: HistoryFormat::Ndjson,
+        history_clients: Some(vec![1, 5]),
+        summary: true,
+        summary_file: Some("summary.json".to_string()),
+    };
+    let parsed = parse(args(&["process", "day1.csv", "--mode", "lenient", "-f", "json",
+        "--output", "accounts.json", "--errors-file", "errors.txt", "--merge", "-c", "engine.json", "--status", "day2.csv",
+        "--save-state", "state.json", "--held-file", "held.jsonl", "--history", "history.ndjson", "--history-format", "ndjson", "--history-clients", "1, 5", "--summary", "--summary-file", "summary.json"]));
+    assert_eq!(parsed, Ok(Command::Process(expected)));
+
+    assert_eq!(parse(args(&[])), Err("Missing input file".to_string()));
+    assert_eq!(parse(args(&["process", "--mode", "fast", "a.csv"])), Err("Invalid mode: fast".to_string()));
+    assert_eq!(parse(args(&["--history-clients", "1,x", "a.csv"])), Err("Invalid client: x".to_string()));
+    assert_eq!(parse(args(&["--help"])), Ok(Command::Help));
+}
+
+/// `reconcile` requires the expected accounts file and accepts tolerances
+/// besides the `process` options, which are unknown to `process`.
+#[test]
+fn parse_reconcile_arguments() {
+    let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>().into_iter();
+
+    let expected = ReconcileArgs {
+        process: ProcessArgs {
+            files: vec!["day1.csv".to_string()],
+            output: None,
+            format: Format::Json,
+            status: false,
+            mode: Mode::Lenient,
+            errors_file: None,
+            merge: false,
+            config: None,
+            audit_file: None,
+            rejections_file: None,
+            held_file: None,
+            save_state: None,
+            history: None,
+            history_format: HistoryFormat::Csv,
+            history_clients: None,
+            summary: false,
+            summary_file: None,
+        },
+        expected: "ledger.csv".to_string(),
+        tolerance: Tolerance { absolute: 0.01, relative: 0.5 },
+    };
+    let parsed = parse(args(&["reconcile", "--expected", "ledger.csv", "--tolerance", "0.01",
+        "--relative-tolerance", "0.5", "-m", "lenient", "-f", "json", "day1.csv"]));
+    assert_eq!(parsed, Ok(Command::Reconcile(expected)));
+
+    assert_eq!(parse(args(&["reconcile", "day1.csv"])), Err("Missing expected accounts file (--expected)".to_string()));
+    assert_eq!(parse(args(&["reconcile", "--expected", "ledger.csv", "--tolerance", "-1", "day1.csv"])), Err("Invalid tolerance: -1".to_string()));
+    assert_eq!(parse(args(&["--expected", "ledger.csv", "day1.csv"])), Err("Unknown option: --expected".to_string()));
+}
+
+/// `simulate` requires the engine state, batches are given with `--batch`
+/// or as bare files. The simulated state is never saved.
+#[test]
+fn parse_simulate_arguments() {
+    let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>().into_iter();
+
+    let expected = SimulateArgs {
+        process: ProcessArgs {
+            files: vec!["chargebacks.csv".to_string(), "resolves.csv".to_string()],
+            output: None,
+            format: Format::Json,
+            status: false,
+            mode: Mode::Strict,
+            errors_file: None,
+            merge: false,
+            config: Some("engine.json".to_string()),
+            audit_file: None,
+            rejections_file: None,
+            held_file: None,
+            save_state: None,
+            history: None,
+            history_format: HistoryFormat::Csv,
+            history_clients: None,
+            summary: false,
+            summary_file: None,
+        },
+        state: "state.json".to_string(),
+    };
+    let parsed = parse(args(&["simulate", "--state", "state.json", "--batch", "chargebacks.csv", "-c", "engine.json", "-f", "json", "resolves.csv"]));
+    assert_eq!(parsed, Ok(Command::Simulate(expected)));
+
+    assert_eq!(parse(args(&["simulate", "--batch", "chargebacks.csv"])), Err("Missing engine state (--state)".to_string()));
+    assert_eq!(parse(args(&["simulate", "--state", "state.json"])), Err("Missing input file".to_string()));
+    assert_eq!(parse(args(&["--batch", "chargebacks.csv"])), Err("Unknown option: --batch".to_string()));
+    assert_eq!(parse(args(&["simulate", "--state", "state.json", "--save-state", "next.json", "a.csv"])),
+        Err("--save-state is not supported by simulate".to_string()));
+}
+
+/// `balances` requires a single point in time, the last one given wins.
+#[test]
+fn parse_balances_arguments() {
+    let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>().into_iter();
+
+    let parsed = parse(args(&["balances", "--as-of-line", "10", "day1.csv"]));
+    assert!(matches!(parsed, Ok(Command::Balances(BalancesArgs { as_of: AsOf::Line(10), .. }))));
+    let parsed = parse(args(&["balances", "--as-of-tx", "3", "--as-of-timestamp", "1700000000", "--merge", "day1.csv"]));
+    assert!(matches!(parsed, Ok(Command::Balances(BalancesArgs { as_of: AsOf::Timestamp(1700000000), process: ProcessArgs { merge: true, .. } }))));
+
+    assert_eq!(parse(args(&["balances", "day1.csv"])), Err("Missing point in time (--as-of-line, --as-of-tx or --as-of-timestamp)".to_string()));
+    assert_eq!(parse(args(&["balances", "--as-of-line", "-1", "day1.csv"])), Err("Invalid value for --as-of-line: -1".to_string()));
+    assert_eq!(parse(args(&["--as-of-line", "1", "day1.csv"])), Err("Unknown option: --as-of-line".to_string()));
+}
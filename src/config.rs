@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use serde_derive::Deserialize;
+use crate::{engine::ClientStats, rules::RuleConfig, FundAccount, Funds, Operation};
+
+/// Engine configuration, loaded from a JSON file.
+///
+/// Every section is optional, an empty object keeps the default behaviour.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub fees: Option<FeeSchedule>,
+    pub limits: Option<Limits>,
+    pub rules: Vec<RuleConfig>,
+    pub auto_lock: Option<AutoLock>,
+    pub dispute_windows: Option<DisputeWindows>,
+}
+
+impl Config {
+    #[cfg(feature = "json")]
+    pub fn from_file(path: &str) -> Result<Config, crate::Errors> {
+        let content = std::fs::read_to_string(path).map_err(|e| crate::Errors::InvalidConfig(format!("{}: {}", path, e)))?;
+        Config::from_json(&content).map_err(|e| crate::Errors::InvalidConfig(format!("{}: {}", path, e)))
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json(content: &str) -> Result<Config, serde_json::Error> {
+        use serde::de::Error as _;
+        let config: Config = serde_json::from_str(content)?;
+        config.validate().map_err(serde_json::Error::custom)?;
+        Ok(config)
+    }
+
+    /// Check settings that deserialize but can never apply.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(limits) = &self.limits {
+            limits.default.validate().map_err(|e| format!("limits.default: {}", e))?;
+            for (client, client_limits) in &limits.clients {
+                client_limits.validate().map_err(|e| format!("limits.clients.{}: {}", client, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fee of an operation: `flat + amount * percentage / 100`,
+/// bounded by the optional `min` and `max` values.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeRule {
+    pub flat: f32,
+    pub percentage: f32,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+impl FeeRule {
+    pub fn fee(&self, amount: f32) -> f32 {
+        let mut fee = self.flat + amount * self.percentage / 100.0;
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+        Funds::get_amount(fee.max(0.0))
+    }
+}
+
+/// Fee rules overriding the default ones for a set of clients.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeTier {
+    pub name: String,
+    pub clients: Vec<u16>,
+    pub deposit: Option<FeeRule>,
+    pub withdrawal: Option<FeeRule>,
+}
+
+/// Fees charged on deposits and withdrawals, credited to `house_account`.
+///
+/// Clients of a tier use the tier rules, falling back to the default
+/// rules for operations the tier does not define.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub house_account: u16,
+    pub deposit: Option<FeeRule>,
+    pub withdrawal: Option<FeeRule>,
+    pub tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    pub fn fee(&self, client: u16, operation: Operation, amount: f32) -> f32 {
+        if client == self.house_account {
+            return 0.0;
+        }
+        let tier = self.tiers.iter().find(|tier| tier.clients.contains(&client));
+        let rule = match operation {
+            Operation::Deposit => tier.and_then(|t| t.deposit.as_ref()).or(self.deposit.as_ref()),
+            Operation::Withdrawal => tier.and_then(|t| t.withdrawal.as_ref()).or(self.withdrawal.as_ref()),
+            _ => None,
+        };
+        rule.map_or(0.0, |rule| rule.fee(amount))
+    }
+}
+
+/// Withdrawal limits of an account. Amounts exclude fees.
+///
+/// Time based limits use the `timestamp` column (seconds): days are UTC days
+/// and the rolling window covers the last `window_seconds`. Rows without
+/// timestamp use the latest timestamp seen by the engine.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WithdrawalLimits {
+    /// Maximum amount of a single withdrawal.
+    pub max_amount: Option<f32>,
+    /// Maximum amount withdrawn per day.
+    pub daily_amount: Option<f32>,
+    pub window_seconds: u64,
+    /// Maximum amount withdrawn within the rolling window.
+    pub window_amount: Option<f32>,
+    /// Maximum number of withdrawals within the rolling window.
+    pub window_count: Option<usize>,
+}
+
+/// Withdrawal limits applied to every client, unless the client
+/// has its own limits in `clients`, which replace the default ones.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub default: WithdrawalLimits,
+    pub clients: HashMap<u16, WithdrawalLimits>,
+}
+
+impl WithdrawalLimits {
+    /// Window limits need a window: with `window_seconds` 0 they would never trigger.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_seconds == 0 && (self.window_amount.is_some() || self.window_count.is_some()) {
+            return Err("window_amount and window_count require a positive window_seconds".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Limits {
+    pub fn for_client(&self, client: u16) -> &WithdrawalLimits {
+        self.clients.get(&client).unwrap_or(&self.default)
+    }
+}
+
+/// Triggers locking an account on suspicious dispute patterns, all optional.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoLock {
+    /// Maximum number of disputes the client can have open at once.
+    pub open_disputes: Option<u32>,
+    /// Maximum disputed amount, as a percentage of the amount deposited by the client.
+    pub disputed_percentage: Option<f32>,
+    /// Maximum number of disputes, resolves and chargebacks referencing
+    /// a transaction of the client under another client ID.
+    pub divergent_client_attempts: Option<u32>,
+}
+
+impl AutoLock {
+    /// Reason of the first trigger reached by the client, if any.
+    pub fn trigger(&self, stats: &ClientStats) -> Option<String> {
+        if let Some(max) = self.open_disputes {
+            if stats.open_disputes > max {
+                return Some(format!("{} open disputes above {}", stats.open_disputes, max));
+            }
+        }
+        if let Some(max) = self.disputed_percentage {
+            let percentage = match stats.deposited > 0.0 {
+                true => stats.disputed / stats.deposited * 100.0,
+                false => 0.0,
+            };
+            if percentage > max {
+                return Some(format!("disputed {:.2}% of deposits above {}%", percentage, max));
+            }
+        }
+        if let Some(max) = self.divergent_client_attempts {
+            if stats.divergent_attempts > max {
+                return Some(format!("{} divergent client ID attempts above {}", stats.divergent_attempts, max));
+            }
+        }
+        None
+    }
+}
+
+/// Maximum age of a disputed transaction, in seconds and/or in rows.
+///
+/// Ages use the `timestamp` column (rows without timestamp use the latest
+/// timestamp seen) and the number of rows processed since the transaction.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeWindow {
+    pub max_age_seconds: Option<u64>,
+    pub max_transactions: Option<u64>,
+}
+
+impl DisputeWindow {
+    pub fn expired(&self, age_seconds: u64, age_transactions: u64) -> bool {
+        self.max_age_seconds.is_some_and(|max| age_seconds > max)
+            || self.max_transactions.is_some_and(|max| age_transactions > max)
+    }
+}
+
+/// Dispute windows by type of the disputed transaction.
+/// Transactions without window can be disputed at any time.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeWindows {
+    pub deposit: Option<DisputeWindow>,
+    pub withdrawal: Option<DisputeWindow>,
+    pub transfer: Option<DisputeWindow>,
+}
+
+impl DisputeWindows {
+    pub fn for_operation(&self, operation: Operation) -> Option<&DisputeWindow> {
+        match operation {
+            Operation::Deposit => self.deposit.as_ref(),
+            Operation::Withdrawal => self.withdrawal.as_ref(),
+            Operation::Transfer => self.transfer.as_ref(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Transactions engine: accounts and transactions state, the rows applied
+//! to it and the reports it records (audit entries, rejections, held rows).
+use std::{collections::{BTreeMap, HashMap}, hash::Hash, mem};
+use serde_derive::{Deserialize, Serialize};
+use crate::{transactions::{apply_reviewed, operate_account}, audit::{AuditEntry, Rejection}, config::Config, error::Errors, events::{Event, Subscriber}, rules::Rule, summary::{Counters, Summary}, velocity::WithdrawalHistory, AccInfo, AccountKey, AccountSnapshot, AccountStatus, FreezeCause, TransactionSnapshot, Txs, Input, Operation};
+
+/// Per client activity, across assets. Only applied transactions are counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClientStats {
+    pub deposits: u32,
+    pub withdrawals: u32,
+    pub disputes: u32,
+    pub open_disputes: u32,
+    pub chargebacks: u32,
+    pub deposited: f32,
+    pub disputed: f32,
+    /// Rows referencing a transaction of the client under another client ID.
+    pub divergent_attempts: u32,
+}
+
+/// Transaction held by a rule, waiting for a manual review.
+#[derive(Debug, Clone, Serialize)]
+pub struct HeldTransaction {
+    pub line: i32,
+    pub rule: String,
+    pub reason: String,
+    pub row: Input,
+}
+
+/// Withdrawals of an account counted by the withdrawal limits, see `EngineState`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithdrawalSnapshot {
+    pub client: u16,
+    pub asset: String,
+    /// Day (timestamp / 86400) and amount withdrawn that day, for daily limits.
+    pub day: u64,
+    pub day_amount: f32,
+    /// Timestamp and amount of the withdrawals in the sliding window.
+    pub window: Vec<(u64, f32)>,
+}
+
+/// Serializable state of an engine, see `Engine::state` and `Engine::restore`.
+///
+/// Held transactions and reports are not part of the state. States saved
+/// without withdrawals restore empty withdrawal limit windows.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EngineState {
+    pub clock: u64,
+    pub sequence: u64,
+    pub accounts: Vec<AccountSnapshot>,
+    pub transactions: Vec<Txs>,
+    pub stats: BTreeMap<u16,ClientStats>,
+    #[serde(default)]
+    pub withdrawals: Vec<WithdrawalSnapshot>,
+}
+
+/// Copy of the whole engine state, see `Engine::checkpoint` and `Engine::rewind`.
+///
+/// Unlike `EngineState`, withdrawal limit windows and held transactions are kept,
+/// but checkpoints cannot be serialized.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    accounts: HashMap<AccountKey,AccInfo>,
+    transactions: HashMap<u32,Txs>,
+    clock: u64,
+    sequence: u64,
+    withdrawals: HashMap<AccountKey,WithdrawalHistory>,
+    stats: HashMap<u16,ClientStats>,
+    held: Vec<HeldTransaction>,
+    counters: Counters,
+}
+
+/// State entries changed by a simulated batch and their value before the
+/// batch, `None` for entries created by the batch. See `Engine::simulate`.
+#[derive(Default)]
+pub(crate) struct Journal {
+    accounts: HashMap<AccountKey,Option<AccInfo>>,
+    transactions: HashMap<u32,Option<Txs>>,
+    withdrawals: HashMap<AccountKey,Option<WithdrawalHistory>>,
+    stats: HashMap<u16,Option<ClientStats>>,
+}
+
+/// Save the value of `key` in `saved`, unless it was saved before.
+fn save_entry<K: Hash + Eq, V: Clone>(saved: &mut HashMap<K,Option<V>>, current: &HashMap<K,V>, key: K) {
+    saved.entry(key).or_insert_with_key(|key| current.get(key).cloned());
+}
+
+/// Put the saved values back in `current`, removing the entries created since.
+fn restore_entries<K: Hash + Eq, V>(current: &mut HashMap<K,V>, saved: HashMap<K,Option<V>>) {
+    for (key, value) in saved {
+        match value {
+            Some(value) => current.insert(key, value),
+            None => current.remove(&key),
+        };
+    }
+}
+
+/// Change of an account by a simulated batch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountDiff {
+    /// `None` when the account was created by the batch.
+    pub before: Option<AccountSnapshot>,
+    pub after: AccountSnapshot,
+}
+
+impl AccountDiff {
+    /// Change of the available, held and total amounts.
+    pub fn change(&self) -> (f32, f32, f32) {
+        let (available, held, total) = self.before.as_ref().map_or((0.0, 0.0, 0.0), |before| (before.available, before.held, before.total));
+        (self.after.available - available, self.after.held - held, self.after.total - total)
+    }
+}
+
+/// Outcome of a simulated batch, see `Engine::simulate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Simulation {
+    /// Accounts changed by the batch, sorted by client and asset.
+    pub accounts: Vec<AccountDiff>,
+    pub rejections: Vec<Rejection>,
+    pub held: Vec<HeldTransaction>,
+    pub audit: Vec<AuditEntry>,
+}
+
+/// Transactions engine state.
+///
+/// Keeps client accounts (per client and asset) and processed transactions,
+/// so several inputs can be applied one after the other against the same state.
+#[derive(Default)]
+pub struct Engine {
+    pub(crate) accounts: HashMap<AccountKey,AccInfo>,
+    pub(crate) transactions: HashMap<u32,Txs>,
+    pub(crate) config: Config,
+    pub(crate) audit: Vec<AuditEntry>,
+    pub(crate) rejections: Vec<Rejection>,
+    /// Latest timestamp seen, used by rows without timestamp.
+    pub(crate) clock: u64,
+    /// Number of rows applied so far.
+    pub(crate) sequence: u64,
+    pub(crate) withdrawals: HashMap<AccountKey,WithdrawalHistory>,
+    pub(crate) rules: Vec<Box<dyn Rule>>,
+    pub(crate) stats: HashMap<u16,ClientStats>,
+    pub(crate) held: Vec<HeldTransaction>,
+    /// Events raised by the row being applied, delivered once it is applied.
+    pub(crate) events: Vec<Event>,
+    pub(crate) subscribers: Vec<Box<dyn Subscriber>>,
+    /// Rows applied so far, for the run summary.
+    pub(crate) counters: Counters,
+    /// Entries to restore once the batch being simulated is applied.
+    pub(crate) journal: Option<Journal>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::default()
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let rules = config.rules.iter().map(|rule| rule.build()).collect();
+        Engine { config, rules, ..Engine::default() }
+    }
+
+    /// Register a rule, evaluated after the configured ones.
+    pub fn add_rule(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    /// Register a subscriber, receiving the events of the rows applied from now on.
+    pub fn subscribe(&mut self, subscriber: Box<dyn Subscriber>) {
+        self.subscribers.push(subscriber);
+    }
+
+    /// Deliver the pending events to the subscribers.
+    fn notify(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            for subscriber in self.subscribers.iter_mut() {
+                subscriber.on_event(&event);
+            }
+        }
+    }
+
+    /// Accounts the row can change: its client and recipient, the accounts
+    /// of the transaction it references and the house account.
+    fn row_accounts(&self, row: &Input) -> Vec<AccountKey> {
+        let asset = row.asset().to_string();
+        let mut keys: Vec<AccountKey> = [row.client, row.to].into_iter().flatten().map(|client| (client, asset.clone())).collect();
+        if let Some(recorded) = row.tx.and_then(|tx| self.transactions.get(&tx)) {
+            keys.extend([recorded.info.client, recorded.info.to].into_iter().flatten().map(|client| (client, recorded.info.asset().to_string())));
+        }
+        if let Some(fees) = &self.config.fees {
+            keys.push((fees.house_account, asset));
+        }
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    /// Accounts the row can change and their balances before it is applied,
+    /// only gathered when subscribers listen to `BalanceChanged` events.
+    fn watch_balances(&self, row: &Input) -> Vec<(AccountKey, Option<AccInfo>)> {
+        if self.subscribers.is_empty() {
+            return Vec::new();
+        }
+        self.row_accounts(row).into_iter().map(|key| {
+            let balances = self.accounts.get(&key).cloned();
+            (key, balances)
+        }).collect()
+    }
+
+    /// Save the entries the row can change, while a batch is simulated.
+    fn journal_row(&mut self, row: &Input) {
+        if self.journal.is_none() {
+            return;
+        }
+        let accounts = self.row_accounts(row);
+        let owner = row.tx.and_then(|tx| self.transactions.get(&tx)).and_then(|recorded| recorded.info.client);
+        let Some(journal) = self.journal.as_mut() else {
+            return;
+        };
+        for key in accounts {
+            save_entry(&mut journal.accounts, &self.accounts, key);
+        }
+        if let Some(tx) = row.tx {
+            save_entry(&mut journal.transactions, &self.transactions, tx);
+        }
+        if let Some(client) = row.client {
+            save_entry(&mut journal.withdrawals, &self.withdrawals, (client, row.asset().to_string()));
+        }
+        for client in [row.client, owner].into_iter().flatten() {
+            save_entry(&mut journal.stats, &self.stats, client);
+        }
+    }
+
+    /// Save an account changed by an administrator, while a batch is simulated.
+    fn journal_account(&mut self, key: &AccountKey) {
+        if let Some(journal) = self.journal.as_mut() {
+            save_entry(&mut journal.accounts, &self.accounts, key.clone());
+        }
+    }
+
+    /// Raise a `BalanceChanged` event for each watched account changed by the row.
+    fn balance_events(&mut self, watched: Vec<(AccountKey, Option<AccInfo>)>, row: (Option<u32>, Option<Operation>), line: i32) {
+        let (Some(tx), Some(operation)) = row else {
+            return;
+        };
+        for (key, before) in watched {
+            match self.accounts.get(&key) {
+                Some(acc) if before.as_ref() != Some(acc) => self.events.push(Event::BalanceChanged {
+                    line,
+                    tx,
+                    operation,
+                    client: key.0,
+                    asset: key.1,
+                    available: acc.available,
+                    held: acc.held,
+                    total: acc.total,
+                    locked: acc.locked(),
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    /// Apply a single row. `line` is only used for locating errors.
+    pub fn apply(&mut self, row: Input, line: i32) -> Result<(), Errors> {
+        if let Some(timestamp) = row.timestamp {
+            self.clock = self.clock.max(timestamp);
+        }
+        self.sequence += 1;
+        self.journal_row(&row);
+        let watched = self.watch_balances(&row);
+        let change = (row.tx, row.op_type);
+        let result = operate_account(row, self, line);
+        self.balance_events(watched, change, line);
+        self.notify();
+        result
+    }
+
+    /// Summary of the rows applied since the engine was created.
+    /// Reviewed transactions are counted once, when they are held.
+    pub fn summary(&self) -> Summary {
+        self.counters.summary(&self.accounts)
+    }
+
+    pub fn accounts(&self) -> &HashMap<AccountKey,AccInfo> {
+        &self.accounts
+    }
+
+    pub fn account(&self, client: u16, asset: &str) -> Option<&AccInfo> {
+        self.accounts.get(&(client, asset.to_string()))
+    }
+
+    pub fn transaction(&self, tx: u32) -> Option<&Txs> {
+        self.transactions.get(&tx)
+    }
+
+    /// Copy of every account, sorted by client and asset.
+    pub fn account_snapshots(&self) -> Vec<AccountSnapshot> {
+        let mut snapshots: Vec<AccountSnapshot> = self.accounts.iter().map(|(key, acc)| AccountSnapshot::new(key, acc)).collect();
+        snapshots.sort_by(|a, b| (a.client, &a.asset).cmp(&(b.client, &b.asset)));
+        snapshots
+    }
+
+    /// Copy of every recorded transaction, sorted by tx.
+    pub fn transaction_snapshots(&self) -> Vec<TransactionSnapshot> {
+        let mut snapshots: Vec<TransactionSnapshot> = self.transactions.values().map(TransactionSnapshot::new).collect();
+        snapshots.sort_by_key(|snapshot| snapshot.tx);
+        snapshots
+    }
+
+    /// Freeze an account on behalf of an administrator.
+    /// Returns false when the account does not exist or is already locked.
+    pub fn freeze(&mut self, client: u16, asset: &str, reason: &str) -> bool {
+        self.journal_account(&(client, asset.to_string()));
+        match self.accounts.get_mut(&(client, asset.to_string())) {
+            Some(account) if !account.locked() => {
+                account.status = AccountStatus::Frozen { cause: FreezeCause::Manual, reason: reason.to_string(), line: None, tx: None };
+                self.events.push(Event::AccountLocked { client, asset: asset.to_string(), status: account.status.clone() });
+                self.notify();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Close an active account without funds.
+    /// Returns false when the account does not exist, is locked or holds funds.
+    pub fn close(&mut self, client: u16, asset: &str) -> bool {
+        self.journal_account(&(client, asset.to_string()));
+        match self.accounts.get_mut(&(client, asset.to_string())) {
+            Some(account) if !account.locked() && account.total == 0.0 => {
+                account.status = AccountStatus::Closed;
+                self.events.push(Event::AccountLocked { client, asset: asset.to_string(), status: AccountStatus::Closed });
+                self.notify();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Copy of the accounts, transactions, client statistics and withdrawal limit windows.
+    pub fn state(&self) -> EngineState {
+        let mut withdrawals: Vec<WithdrawalSnapshot> = self.withdrawals.iter().map(|(key, history)| history.snapshot(key)).collect();
+        withdrawals.sort_by(|a, b| (a.client, &a.asset).cmp(&(b.client, &b.asset)));
+        EngineState {
+            clock: self.clock,
+            sequence: self.sequence,
+            accounts: self.account_snapshots(),
+            transactions: self.transactions.values().cloned().collect(),
+            stats: self.stats.iter().map(|(client, stats)| (*client, *stats)).collect(),
+            withdrawals,
+        }
+    }
+
+    /// Replace the accounts, transactions, client statistics and withdrawal
+    /// limit windows with `state`, e.g. saved by a previous run.
+    pub fn restore(&mut self, state: EngineState) {
+        self.clock = state.clock;
+        self.sequence = state.sequence;
+        self.accounts = state.accounts.iter().map(|snapshot| (snapshot.key(), snapshot.account())).collect();
+        self.transactions = state.transactions.into_iter().filter_map(|txs| Some((txs.info.tx?, txs))).collect();
+        self.stats = state.stats.into_iter().collect();
+        self.withdrawals = state.withdrawals.into_iter().map(WithdrawalHistory::restore).collect();
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            accounts: self.accounts.clone(),
+            transactions: self.transactions.clone(),
+            clock: self.clock,
+            sequence: self.sequence,
+            withdrawals: self.withdrawals.clone(),
+            stats: self.stats.clone(),
+            held: self.held.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+
+    /// Go back to the state of `checkpoint`. Configuration, rules,
+    /// subscribers and pending reports are kept.
+    pub fn rewind(&mut self, checkpoint: &Checkpoint) {
+        let checkpoint = checkpoint.clone();
+        self.accounts = checkpoint.accounts;
+        self.transactions = checkpoint.transactions;
+        self.clock = checkpoint.clock;
+        self.sequence = checkpoint.sequence;
+        self.withdrawals = checkpoint.withdrawals;
+        self.stats = checkpoint.stats;
+        self.held = checkpoint.held;
+        self.counters = checkpoint.counters;
+    }
+
+    /// Apply a batch to the engine and return its outcome, then undo it,
+    /// leaving the engine unchanged.
+    ///
+    /// The batch is applied in place: the accounts, transactions, withdrawal
+    /// windows and client statistics a row can change are saved before the
+    /// row is applied and put back once the batch is applied, so a simulation
+    /// costs in proportion to the batch, not to the engine state.
+    ///
+    /// `batch` applies rows to the engine, e.g. with `process_csv`, and its
+    /// result is returned along with the simulation. Rules are evaluated as
+    /// usual, while the engine subscribers are not notified: `batch` can
+    /// subscribe to the events of the batch, until it returns. Reports, held
+    /// transactions and the summary only cover the batch. `batch` applies
+    /// rows and freezes or closes accounts, restoring a state or rewinding
+    /// to a checkpoint is not undone.
+    pub fn simulate<T, E, F>(&mut self, batch: F) -> Result<(Simulation, T), E>
+    where
+        F: FnOnce(&mut Engine) -> Result<T, E>,
+    {
+        //Simulations nested in a batch are undone before the outer journal goes on
+        let outer = self.journal.replace(Journal::default());
+        let (clock, sequence) = (self.clock, self.sequence);
+        let subscribers = mem::take(&mut self.subscribers);
+        let audit = mem::take(&mut self.audit);
+        let rejections = mem::take(&mut self.rejections);
+        let held = mem::take(&mut self.held);
+        let counters = mem::take(&mut self.counters);
+
+        let applied = batch(self);
+
+        let journal = mem::replace(&mut self.journal, outer).unwrap_or_default();
+        let mut accounts: Vec<AccountDiff> = journal.accounts.iter()
+            .filter_map(|(key, before)| match self.accounts.get(key) {
+                Some(after) if before.as_ref() != Some(after) => Some(AccountDiff {
+                    before: before.as_ref().map(|before| AccountSnapshot::new(key, before)),
+                    after: AccountSnapshot::new(key, after),
+                }),
+                _ => None,
+            })
+            .collect();
+        accounts.sort_by(|a, b| (a.after.client, &a.after.asset).cmp(&(b.after.client, &b.after.asset)));
+        let simulation = Simulation {
+            accounts,
+            rejections: mem::replace(&mut self.rejections, rejections),
+            held: mem::replace(&mut self.held, held),
+            audit: mem::replace(&mut self.audit, audit),
+        };
+        restore_entries(&mut self.accounts, journal.accounts);
+        restore_entries(&mut self.transactions, journal.transactions);
+        restore_entries(&mut self.withdrawals, journal.withdrawals);
+        restore_entries(&mut self.stats, journal.stats);
+        (self.clock, self.sequence) = (clock, sequence);
+        self.subscribers = subscribers;
+        self.counters = counters;
+        Ok((simulation, applied?))
+    }
+
+    pub fn client_stats(&self, client: u16) -> ClientStats {
+        self.stats.get(&client).copied().unwrap_or_default()
+    }
+
+    pub fn held(&self) -> &[HeldTransaction] {
+        &self.held
+    }
+
+    /// Take the transactions held for review since the last call.
+    pub fn take_held(&mut self) -> Vec<HeldTransaction> {
+        std::mem::take(&mut self.held)
+    }
+
+    /// Apply a reviewed transaction, without evaluating the rules again.
+    pub fn apply_reviewed(&mut self, held: HeldTransaction) -> Result<(), Errors> {
+        self.journal_row(&held.row);
+        let watched = self.watch_balances(&held.row);
+        let change = (held.row.tx, held.row.op_type);
+        let result = apply_reviewed(held.row, self, held.line);
+        self.balance_events(watched, change, held.line);
+        self.notify();
+        result
+    }
+
+    /// Take the audit entries recorded since the last call.
+    pub fn drain_audit(&mut self) -> Vec<AuditEntry> {
+        std::mem::take(&mut self.audit)
+    }
+
+    /// Take the transactions rejected since the last call.
+    pub fn drain_rejections(&mut self) -> Vec<Rejection> {
+        std::mem::take(&mut self.rejections)
+    }
+
+    pub fn into_accounts(self) -> HashMap<AccountKey,AccInfo> {
+        self.accounts
+    }
+}
@@ -1,19 +1,49 @@
 use core::fmt;
 
-#[derive(PartialEq,Debug)]
+#[derive(PartialEq,Debug,Clone)]
 
-/// Enum of predictable errors. Each error should provide a 
+/// Enum of predictable errors. Each error should provide a
 /// specific error message, indicating the line raising the bug.
 pub enum Errors {
     InvalidOperation(String),
     InvalidClient(String),
     InvalidTx(String),
     InvalidAmount(String),
+    InvalidRecipient(String),
+    InvalidRecord(String),
+    InvalidInput(String),
+    OutOfOrder(String),
+    InvalidConfig(String),
     ConflictTransaction(String),
     SecurityErrDivergentClientId(String)
 
 }
 
+impl Errors {
+    /// Errors that could indicate a critical bug or a security incident,
+    /// as opposed to malformed input from our partners.
+    pub fn is_security(&self) -> bool {
+        matches!(self, Errors::ConflictTransaction(_) | Errors::SecurityErrDivergentClientId(_))
+    }
+
+    /// Name of the error in snake case, e.g. for counting errors by kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Errors::InvalidOperation(_) => "invalid_operation",
+            Errors::InvalidClient(_) => "invalid_client",
+            Errors::InvalidTx(_) => "invalid_tx",
+            Errors::InvalidAmount(_) => "invalid_amount",
+            Errors::InvalidRecipient(_) => "invalid_recipient",
+            Errors::InvalidRecord(_) => "invalid_record",
+            Errors::InvalidInput(_) => "invalid_input",
+            Errors::OutOfOrder(_) => "out_of_order",
+            Errors::InvalidConfig(_) => "invalid_config",
+            Errors::ConflictTransaction(_) => "conflict_transaction",
+            Errors::SecurityErrDivergentClientId(_) => "divergent_client_id",
+        }
+    }
+}
+
 impl fmt::Display for Errors {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &*self {
@@ -21,8 +51,15 @@ impl fmt::Display for Errors {
             Errors::InvalidClient(line) => write!(f, "Invalid Client at line: {}",line),
             Errors::InvalidTx(line) => write!(f, "Invalid Tx at line: {}",line),
             Errors::InvalidAmount(line) => write!(f, "Invalid Amount at line: {}",line),
+            Errors::InvalidRecipient(line) => write!(f, "Invalid Recipient at line: {}",line),
+            Errors::InvalidRecord(line) => write!(f, "Invalid Record at line: {}",line),
+            Errors::InvalidInput(reason) => write!(f, "Unable to read input: {}",reason),
+            Errors::OutOfOrder(line) => write!(f, "Out of order Timestamp at line: {}",line),
+            Errors::InvalidConfig(reason) => write!(f, "Invalid configuration: {}",reason),
             Errors::ConflictTransaction(line) => write!(f, "Conflicting Transaction at line: {}",line),
             Errors::SecurityErrDivergentClientId(line) => write!(f, "Divergent Transaction and Client ID at line: {}",line),
        }
     }
 }
+
+impl std::error::Error for Errors {}
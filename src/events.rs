@@ -0,0 +1,80 @@
+//! Events raised by the engine while applying rows, delivered to the
+//! subscribers registered with `Engine::subscribe` once the row is applied.
+use std::sync::mpsc::{self, Receiver, Sender};
+use serde_derive::Serialize;
+use crate::{AccountStatus, Operation};
+
+/// Account and transaction events.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// First transaction of the client on the asset, or first fee credited to the house account.
+    AccountCreated {
+        line: i32,
+        client: u16,
+        asset: String,
+    },
+    /// Account frozen (chargeback, auto lock trigger, administrator) or closed.
+    AccountLocked {
+        client: u16,
+        asset: String,
+        status: AccountStatus,
+    },
+    /// Funds held by a dispute, partial disputes raise one event each.
+    DisputeOpened {
+        line: i32,
+        tx: u32,
+        client: u16,
+        asset: String,
+        amount: f32,
+    },
+    ChargebackApplied {
+        line: i32,
+        tx: u32,
+        client: u16,
+        asset: String,
+        amount: f32,
+    },
+    /// Balances of an account changed by a row, one event per changed account.
+    BalanceChanged {
+        line: i32,
+        tx: u32,
+        operation: Operation,
+        client: u16,
+        asset: String,
+        available: f32,
+        held: f32,
+        total: f32,
+        locked: bool,
+    },
+}
+
+/// Receives the engine events, in the order they were raised.
+pub trait Subscriber: Send {
+    fn on_event(&mut self, event: &Event);
+}
+
+/// Subscriber forwarding events to a channel, for consumers running on other threads.
+///
+/// Events are dropped once the receiver is gone.
+pub struct ChannelSubscriber {
+    sender: Sender<Event>,
+}
+
+impl ChannelSubscriber {
+    pub fn new(sender: Sender<Event>) -> Self {
+        ChannelSubscriber { sender }
+    }
+}
+
+impl Subscriber for ChannelSubscriber {
+    fn on_event(&mut self, event: &Event) {
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+/// Channel subscriber and the receiving end of its events.
+pub fn channel() -> (ChannelSubscriber, Receiver<Event>) {
+    let (sender, receiver) = mpsc::channel();
+    (ChannelSubscriber::new(sender), receiver)
+}
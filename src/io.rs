@@ -0,0 +1,291 @@
+//! Input handling: file expansion, compressed inputs, processing several
+//! files in order and merging timestamped streams.
+use std::{cmp::Reverse, collections::BinaryHeap, fmt, fs::{self, File}, io::{self, BufRead, BufReader, Read}, path::{Path, PathBuf}};
+use flate2::read::MultiGzDecoder;
+use rust_csv::DeserializeRecordsIntoIter;
+use crate::{csv_reader, process_csv, replay::LogRows, Engine, Errors, Input, Mode};
+
+/// Error annotated with the input file it originated from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct InputError {
+    pub origin: String,
+    pub error: Errors,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.origin, self.error)
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// Match a file name against a pattern supporting `*` and `?` wildcards.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            wildcard_match(&pattern[1..], name) || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) => p == n && wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Regular, non hidden files of `dir` whose name matches `pattern`, sorted by name.
+fn list_files(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, Errors> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let entries = fs::read_dir(dir).map_err(|e| Errors::InvalidInput(format!("{}: {}", dir.display(), e)))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| Errors::InvalidInput(format!("{}: {}", dir.display(), e)))?.path();
+        let name: Vec<char> = match path.file_name() {
+            Some(name) => name.to_string_lossy().chars().collect(),
+            None => continue,
+        };
+        if path.is_file() && name.first() != Some(&'.') && wildcard_match(&pattern, &name) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Expand the input arguments into the ordered list of files to process.
+///
+/// Arguments keep their order. Directories expand to the files they contain
+/// and `*`/`?` patterns in the file name expand to the matching files, both
+/// sorted by name so date-named files are processed chronologically.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, Errors> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = PathBuf::from(input);
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if path.is_dir() {
+            files.extend(list_files(&path, "*")?);
+        } else if name.contains('*') || name.contains('?') {
+            let dir = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let matches = list_files(&dir, &name)?;
+            if matches.is_empty() {
+                return Err(Errors::InvalidInput(format!("{}: no matching files", input)));
+            }
+            files.extend(matches);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression of an input file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression from the first bytes of the file,
+    /// falling back to the `.gz`/`.zst` extensions.
+    pub fn detect(path: &Path, head: &[u8]) -> Compression {
+        if head.starts_with(&GZIP_MAGIC) {
+            return Compression::Gzip;
+        }
+        if head.starts_with(&ZSTD_MAGIC) {
+            return Compression::Zstd;
+        }
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Open an input file, decompressing gzip and zstd files on the fly.
+///
+/// Decoding is streamed, the file is never fully loaded in memory.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(path, reader.fill_buf()?);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+    })
+}
+
+/// Process `files` in order against the same engine state.
+///
+/// Errors are annotated with the file they originated from. In strict mode
+/// the first error is returned, in lenient mode unreadable files and invalid
+/// rows are skipped and returned once every file has been processed.
+pub fn process_files(engine: &mut Engine, files: &[PathBuf], mode: Mode) -> Result<Vec<InputError>, InputError> {
+    let mut errors = Vec::new();
+    for file in files {
+        let origin = file.display().to_string();
+        let processed = open_input(file)
+            .map_err(|e| Errors::InvalidInput(e.to_string()))
+            .and_then(|f| process_csv(engine, f, mode));
+        match processed {
+            Ok(skipped) => errors.extend(skipped.into_iter().map(|error| InputError { origin: origin.clone(), error })),
+            Err(error) => {
+                let error = InputError { origin, error };
+                match mode {
+                    Mode::Strict => return Err(error),
+                    Mode::Lenient => errors.push(error),
+                }
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Single input stream of a `MergeReader`, holding its next row.
+struct Stream<R: Read> {
+    records: DeserializeRecordsIntoIter<R, Input>,
+    line: i32,
+    last: u64,
+    head: Option<(i32, Result<Input, Errors>)>,
+}
+
+impl<R: Read> Stream<R> {
+    /// Read the next row of the stream, returning its merge timestamp.
+    ///
+    /// Rows without a timestamp inherit the one of the previous row. Rows
+    /// going back in time are returned as `OutOfOrder` errors and keep the
+    /// stream timestamp, so they do not alter the merge order.
+    fn advance(&mut self) -> Option<u64> {
+        let result = self.records.next()?;
+        self.line += 1;
+        let line = self.line.to_string();
+        let row = match result {
+            Ok(row) => match row.timestamp {
+                Some(timestamp) if timestamp < self.last => Err(Errors::OutOfOrder(line)),
+                Some(timestamp) => {
+                    self.last = timestamp;
+                    Ok(row)
+                }
+                None => Ok(row),
+            },
+            Err(_) => Err(Errors::InvalidRecord(line)),
+        };
+        self.head = Some((self.line, row));
+        Some(self.last)
+    }
+}
+
+/// K-way merge of several CSV transaction streams ordered by timestamp.
+///
+/// Each stream is expected to be chronological on its own. Rows with the same
+/// timestamp are returned in stream order, then in line order, so the merge
+/// is deterministic. Yields `(stream index, line, row)`.
+pub struct MergeReader<R: Read> {
+    streams: Vec<Stream<R>>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl<R: Read> MergeReader<R> {
+    pub fn new(readers: Vec<R>) -> Self {
+        let mut streams = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (index, reader) in readers.into_iter().enumerate() {
+            let mut stream = Stream { records: csv_reader(reader).into_deserialize(), line: 0, last: 0, head: None };
+            if let Some(timestamp) = stream.advance() {
+                heap.push(Reverse((timestamp, index)));
+            }
+            streams.push(stream);
+        }
+        MergeReader { streams, heap }
+    }
+}
+
+impl<R: Read> Iterator for MergeReader<R> {
+    type Item = (usize, i32, Result<Input, Errors>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, index)) = self.heap.pop()?;
+        let stream = &mut self.streams[index];
+        let (line, row) = stream.head.take()?;
+        if let Some(timestamp) = stream.advance() {
+            self.heap.push(Reverse((timestamp, index)));
+        }
+        Some((index, line, row))
+    }
+}
+
+/// Interleave several named streams by timestamp and apply them to `engine`.
+///
+/// Errors are annotated with the name of the stream raising them. In strict
+/// mode the first error is returned, in lenient mode invalid and out of order
+/// rows are skipped and returned once every stream is exhausted.
+pub fn process_merged<R: Read>(engine: &mut Engine, inputs: Vec<(String, R)>, mode: Mode) -> Result<Vec<InputError>, InputError> {
+    let (origins, readers): (Vec<String>, Vec<R>) = inputs.into_iter().unzip();
+    let mut errors = Vec::new();
+    for (index, line, row) in MergeReader::new(readers) {
+        if let Err(error) = row.and_then(|row| engine.apply(row, line)) {
+            let error = InputError { origin: origins[index].clone(), error };
+            match mode {
+                Mode::Strict => return Err(error),
+                Mode::Lenient => errors.push(error),
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Open `files` and interleave them by timestamp, see `process_merged`.
+///
+/// Like `process_files`, unreadable files are returned as errors in strict
+/// mode and skipped in lenient mode, their errors coming first.
+pub fn merge_files(engine: &mut Engine, files: &[PathBuf], mode: Mode) -> Result<Vec<InputError>, InputError> {
+    let mut inputs = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        let origin = file.display().to_string();
+        match open_input(file) {
+            Ok(f) => inputs.push((origin, f)),
+            Err(e) => {
+                let error = InputError { origin, error: Errors::InvalidInput(e.to_string()) };
+                match mode {
+                    Mode::Strict => return Err(error),
+                    Mode::Lenient => errors.push(error),
+                }
+            }
+        }
+    }
+    errors.extend(process_merged(engine, inputs, mode)?);
+    Ok(errors)
+}
+
+/// Stream the rows of `files` in processing order, one file after the other
+/// or interleaved by timestamp with `merge`, see `process_files` and `merge_files`.
+///
+/// Rows are paired with the index of their file and their line in it,
+/// invalid rows are kept as errors.
+pub fn stream_rows(files: &[PathBuf], merge: bool) -> Result<LogRows, InputError> {
+    let mut inputs = Vec::new();
+    for file in files {
+        match open_input(file) {
+            Ok(f) => inputs.push(f),
+            Err(e) => return Err(InputError { origin: file.display().to_string(), error: Errors::InvalidInput(e.to_string()) }),
+        }
+    }
+    if merge {
+        return Ok(Box::new(MergeReader::new(inputs).map(|(index, line, row)| (index, (line, row)))));
+    }
+    Ok(Box::new(inputs.into_iter().enumerate().flat_map(|(index, input)| {
+        csv_reader(input).into_deserialize::<Input>().enumerate().map(move |(row, result)| {
+            let line = row as i32 + 1;
+            (index, (line, result.map_err(|_| Errors::InvalidRecord(line.to_string()))))
+        })
+    })))
+}
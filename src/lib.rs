@@ -1,129 +1,212 @@
+#[cfg(feature = "csv")]
 extern crate rust_csv;
-use std::{fs::File, collections::HashMap};
-use serde_derive::Deserialize;
+#[cfg(any(feature = "csv", feature = "json"))]
+use std::collections::HashMap;
+#[cfg(feature = "csv")]
+use std::{io::Read, path::Path};
+#[cfg(feature = "json")]
+use serde_derive::Serialize;
 mod transactions;
 mod error;
-use std::io::BufReader;
+pub mod engine;
+pub mod model;
+pub mod audit;
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod config;
+pub mod events;
+#[cfg(feature = "csv")]
+pub mod io;
+#[cfg(feature = "csv")]
+pub mod reconcile;
+pub mod replay;
+pub mod rules;
+pub mod summary;
+mod velocity;
+pub mod workload;
+#[cfg(feature = "csv")]
 use rust_csv::{ReaderBuilder, Trim};
-use crate::{transactions::operate_account};
-
-/// Struct for processing CSV fields.
-/// 
-/// Invalid Fields are filtered and sanitized by the application.
-#[derive(Deserialize)]
-pub struct Input{
-    #[serde(rename = "type",deserialize_with = "rust_csv::invalid_option")]
-    op_type: Option<Operation>,
-    #[serde(deserialize_with = "rust_csv::invalid_option")]
-    client: Option<u16>,
-    #[serde(deserialize_with = "rust_csv::invalid_option")]
-    tx: Option<u32>,
-    #[serde(deserialize_with = "rust_csv::invalid_option")]
-    amount: Option<f32>
-}
-
-/// Struct used for keeping dispute information of Transactions
-/// 
-/// This struct is used to keep track of disputes in chargeback 
-/// and resolve operations.
-
-pub struct Txs{
-    info: Input,
-    in_dispute: bool
-}
-
-/// Available operations for the Wallet.
-
-#[derive(Debug, Deserialize,PartialEq,Clone, Copy)]
-#[serde(rename_all = "lowercase")]
-pub enum Operation{
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback
+pub use crate::{engine::{AccountDiff, ClientStats, Engine, EngineState, HeldTransaction, Simulation, WithdrawalSnapshot}, error::Errors};
+pub use crate::model::{AccInfo, AccountKey, AccountSnapshot, AccountStatus, FreezeCause, Funds, Input, Operation, TransactionSnapshot, TxStatus, Txs, DEFAULT_ASSET};
+pub(crate) use crate::model::FundAccount;
+
+/// How invalid rows are handled while processing an input.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    /// Stop at the first invalid row.
+    Strict,
+    /// Skip invalid rows and keep processing.
+    Lenient,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Funds(f64);
-
-/// Trait used to ensure 4 decimal places in the stored amount
-/// 
-/// Avoids importing large crates (e.g. rust_decimal) for a simple operation.
-/// 
-/// WARNING: the application does not currently support rounding up/down, only performing truncation.
-/// This is a known issue and depending on application requirements should be addressed.
-trait FundAccount{
-    fn get_amount(amount: f32) -> f32;
+/// CSV reader accepting whitespaces and missing trailing columns.
+#[cfg(feature = "csv")]
+pub(crate) fn csv_reader<R: Read>(reader: R) -> rust_csv::Reader<R> {
+    ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(reader)
 }
 
-impl FundAccount for Funds {
-
-    fn get_amount(amount: f32) -> f32 {
-        (amount * 10000.0).round() / 10000.0
+/// Read CSV transactions from `reader` and apply them to `engine`.
+///
+/// In strict mode the first error is returned. In lenient mode invalid rows
+/// are skipped and their errors are returned once the input is exhausted.
+#[cfg(feature = "csv")]
+pub fn process_csv<R: Read>(engine: &mut Engine, reader: R, mode: Mode) -> Result<Vec<Errors>, Errors> {
+    let mut rd = csv_reader(reader);
+    let mut errors = Vec::new();
+    for (index, result) in rd.deserialize::<Input>().enumerate() {
+        let line = index as i32 + 1;
+        let applied = match result {
+            Ok(tr) => engine.apply(tr, line),
+            Err(_) => Err(Errors::InvalidRecord(line.to_string())),
+        };
+        if let Err(error) = applied {
+            match mode {
+                Mode::Strict => return Err(error),
+                Mode::Lenient => errors.push(error),
+            }
+        }
     }
+    Ok(errors)
 }
 
-/// Struct used for storing account information: 
-/// 
-/// Available, Held and Total amount. Also keeps  
-/// track of the account state (locked/not locked). 
-
-pub struct AccInfo{
-    available: f32,
-    held: f32,
-    total: f32,
-    locked: bool
-}
-
-/// if is_csv == true: process csv input
+/// if is_csv == true: process csv input (gzip and zstd files are decompressed on the fly)
 /// if is_csv == false: process string input as csv entry
+///
+/// Returns the `DEFAULT_ASSET` accounts, use `Engine` for multi-asset inputs.
+#[cfg(feature = "csv")]
 pub fn csv_read(input: &str, is_csv: bool) -> Result<HashMap<u16,AccInfo>, String> {
-    match is_csv{
+    let mut engine = Engine::new();
+    let processed = match is_csv{
         true => {
-            let f = File::open(input).unwrap();
-            let mut accs:HashMap<u16,AccInfo> = HashMap::new();
-            let mut tx:HashMap<u32,Txs> = HashMap::new();
-            let mut rd = ReaderBuilder::new()
-                .trim(Trim::All)
-                .flexible(true)
-                .from_reader(BufReader::new(f));
-            let mut it = 1;
-            for result in rd.deserialize::<Input>() {
-                let tr: Input = result.unwrap();
-                match operate_account(tr, &mut accs, it, &mut tx){
-                    Ok(()) => {it = it + 1},
-                    Err(error) => return Err(error),
-                }
-            }
-            Ok(accs)
-        },
-        false => {
-            let mut accs:HashMap<u16,AccInfo> = HashMap::new();
-            let mut tx:HashMap<u32,Txs> = HashMap::new();
-            let mut rdr = ReaderBuilder::new().trim(Trim::All).from_reader(input.as_bytes());
-            let mut line = 1;
-            for result in rdr.deserialize::<Input>() {
-                let tr: Input = result.unwrap();
-                match operate_account(tr, &mut accs, line, &mut tx){
-                    Ok(()) => {line = line +1},
-                    Err(error) => return Err(error),
-                }
-            }
-            Ok(accs)
+            let f = io::open_input(Path::new(input)).map_err(|e| Errors::InvalidInput(format!("{}: {}", input, e)).to_string())?;
+            process_csv(&mut engine, f, Mode::Strict)
         },
+        false => process_csv(&mut engine, input.as_bytes(), Mode::Strict),
+    };
+    match processed {
+        Ok(_) => Ok(engine.into_accounts()
+            .into_iter()
+            .filter(|((_, asset), _)| asset == DEFAULT_ASSET)
+            .map(|((client, _), acc)| (client, acc))
+            .collect()),
+        Err(error) => Err(error.to_string()),
     }
 }
 
 /// Receive account details and format into csv table
+#[cfg(feature = "csv")]
 pub fn fmt_output(accounts:HashMap<u16,AccInfo>)->String{
     let mut output = "client, available, held, total, locked".to_string();
     for (key, value) in accounts.into_iter() {
-        output = format!("{}\n{}, {}, {}, {}, {}",output,key,value.available,value.held,value.total,value.locked.to_string());
+        output = format!("{}\n{}, {}, {}, {}, {}",output,key,value.available,value.held,value.total,value.locked());
+    }
+    output
+}
+
+/// Quote a CSV field when needed.
+#[cfg(feature = "csv")]
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+/// Status columns of an account: status, reason, line, tx.
+#[cfg(feature = "csv")]
+fn fmt_status(status: &AccountStatus) -> String {
+    match status {
+        AccountStatus::Frozen { reason, line, tx, .. } => {
+            let line = line.map(|line| line.to_string()).unwrap_or_default();
+            let tx = tx.map(|tx| tx.to_string()).unwrap_or_default();
+            format!("{}, {}, {}, {}", status.name(), csv_field(reason), line, tx)
+        }
+        _ => format!("{}, , , ", status.name()),
+    }
+}
+
+/// Receive multi-asset account details and format into csv table.
+///
+/// The asset column is only added when accounts hold other assets than `DEFAULT_ASSET`.
+/// With `with_status`, the status, reason, line and tx of frozen accounts are added.
+#[cfg(feature = "csv")]
+pub fn fmt_asset_output(accounts:HashMap<AccountKey,AccInfo>, with_status: bool)->String{
+    let with_asset = accounts.keys().any(|(_, asset)| asset != DEFAULT_ASSET);
+    if !with_asset && !with_status {
+        return fmt_output(accounts.into_iter().map(|((client, _), acc)| (client, acc)).collect());
+    }
+    let mut output = match with_asset {
+        true => "client, asset, available, held, total, locked".to_string(),
+        false => "client, available, held, total, locked".to_string(),
+    };
+    if with_status {
+        output.push_str(", status, reason, line, tx");
+    }
+    for ((client, asset), value) in accounts.into_iter() {
+        output = match with_asset {
+            true => format!("{}\n{}, {}, {}, {}, {}, {}",output,client,asset,value.available,value.held,value.total,value.locked()),
+            false => format!("{}\n{}, {}, {}, {}, {}",output,client,value.available,value.held,value.total,value.locked()),
+        };
+        if with_status {
+            output = format!("{}, {}",output,fmt_status(&value.status));
+        }
+    }
+    output
+}
+
+/// Format the accounts changed by a simulation into a csv table,
+/// with the resulting amounts and their change.
+#[cfg(feature = "csv")]
+pub fn fmt_simulation(simulation: &Simulation) -> String {
+    let mut output = "client, asset, available, held, total, locked, available_change, held_change, total_change".to_string();
+    for diff in simulation.accounts.iter() {
+        let (available, held, total) = diff.change();
+        let after = &diff.after;
+        output = format!("{}\n{}, {}, {}, {}, {}, {}, {}, {}, {}",output,after.client,after.asset,after.available,after.held,after.total,after.locked,available,held,total);
     }
     output
 }
 
+/// Output row used for JSON formatting.
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct AccountRow {
+    client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset: Option<String>,
+    available: f32,
+    held: f32,
+    total: f32,
+    locked: bool,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    status: Option<AccountStatus>,
+}
+
+/// Receive account details and format into a JSON array, one entry per client and asset.
+///
+/// The asset field is only added when accounts hold other assets than `DEFAULT_ASSET`.
+/// With `with_status`, the status, reason, line and tx of frozen accounts are added.
+#[cfg(feature = "json")]
+pub fn fmt_output_json(accounts:HashMap<AccountKey,AccInfo>, with_status: bool)->String{
+    let single_asset = accounts.keys().all(|(_, asset)| asset == DEFAULT_ASSET);
+    let rows: Vec<AccountRow> = accounts
+        .into_iter()
+        .map(|((client, asset), acc)| AccountRow {
+            client,
+            asset: if single_asset { None } else { Some(asset) },
+            available: acc.available,
+            held: acc.held,
+            total: acc.total,
+            locked: acc.locked(),
+            status: if with_status { Some(acc.status) } else { None },
+        })
+        .collect();
+    serde_json::to_string_pretty(&rows).unwrap()
+}
+
 
 /// Default Input.
 /// 
@@ -146,6 +229,7 @@ pub fn fmt_output(accounts:HashMap<u16,AccInfo>)->String{
 /// `1, 1.5, 0.0, 1.5, false`
 /// 
 /// `2, 2.0, 0.0, 2.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn default_test() {
     let input = "
@@ -160,8 +244,8 @@ fn default_test() {
 
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
 
-    expected_hashmap.insert(1, AccInfo { available: 1.5, held: 0.0, total: 1.5, locked: false });
-    expected_hashmap.insert(2, AccInfo { available: 2.0, held: 0.0, total: 2.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 1.5, held: 0.0, total: 1.5, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 2.0, held: 0.0, total: 2.0, status: AccountStatus::Active });
 
 
     let output = match csv_read(&input, is_csv){
@@ -176,7 +260,7 @@ fn default_test() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -209,6 +293,7 @@ fn default_test() {
 /// `1, 2.0124,0,2.0124,false`
 /// 
 /// `2, 0.8891, 0.0, 0.8891, false`
+#[cfg(feature = "csv")]
 #[test]
 fn four_decimal_places() {
     let input = "
@@ -222,8 +307,8 @@ fn four_decimal_places() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 2.0124, held: 0.0, total: 2.0124, locked: false });
-    expected_hashmap.insert(2, AccInfo { available: 0.5803, held: 0.0, total: 0.5803, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 2.0124, held: 0.0, total: 2.0124, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 0.5803, held: 0.0, total: 0.5803, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -237,7 +322,7 @@ fn four_decimal_places() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -271,6 +356,7 @@ fn four_decimal_places() {
 /// 
 /// `2, 2, 0, 2, false`
 /// 
+#[cfg(feature = "csv")]
 #[test]
 fn successful_dispute() {
    
@@ -287,8 +373,8 @@ fn successful_dispute() {
     let is_csv = false;
 
 
-    expected_hashmap.insert(1, AccInfo { available: 0.5, held: 1.0, total: 1.5, locked: false });
-    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 0.5, held: 1.0, total: 1.5, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -302,7 +388,7 @@ fn successful_dispute() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -337,6 +423,7 @@ fn successful_dispute() {
 /// `1, 1.5, 0.0, 1.5, false`
 /// 
 /// `2, 0.0, 0.0, 0.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn successful_resolve() {
     let input = "
@@ -352,8 +439,8 @@ fn successful_resolve() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 1.5, held: 0.0, total: 1.5, locked: false });
-    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 1.5, held: 0.0, total: 1.5, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -367,7 +454,7 @@ fn successful_resolve() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -402,6 +489,7 @@ fn successful_resolve() {
 /// `1, 0.5, 0.0, 0.5, true`
 /// 
 /// `2, 0.0, 0.0, 0.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn successful_chargeback() {
 
@@ -418,8 +506,8 @@ fn successful_chargeback() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 0.5, held: 0.0, total: 0.5, locked: true });
-    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 0.5, held: 0.0, total: 0.5, status: AccountStatus::charged_back(7, 1) });
+    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -433,7 +521,7 @@ fn successful_chargeback() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -480,6 +568,7 @@ fn successful_chargeback() {
 /// `0.5, 0.0, 0.5, true`
 /// 
 /// `0.0, 0.0, 0.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn frozen_account_test() {
     let input = "type, client, tx, amount
@@ -500,8 +589,8 @@ fn frozen_account_test() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 0.5, held: 0.0, total: 0.5, locked: true });
-    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 0.5, held: 0.0, total: 0.5, status: AccountStatus::charged_back(7, 1) });
+    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -515,7 +604,7 @@ fn frozen_account_test() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -527,6 +616,7 @@ fn frozen_account_test() {
 /// pointing the location for the error.
 /// This unexpected operation should be raised as an alert in a real scenario, since it could indicate
 /// a bug within the application or a security incident.
+#[cfg(feature = "csv")]
 #[test]
 fn invalid_operation() {
     let input = "
@@ -550,6 +640,7 @@ fn invalid_operation() {
 /// Type mismatch on client column should raise an error message (e.g string value instead of integer). 
 /// This unexpected operation should be raised as an alert in a real scenario, since it could indicate
 /// a bug within the application or a security incident.
+#[cfg(feature = "csv")]
 #[test]
 fn invalid_clientid() {
     let input = "
@@ -573,6 +664,7 @@ fn invalid_clientid() {
 /// Type mismatch on transaction column should raise an error message (e.g string value instead of integer). 
 /// This unexpected operation should be raised as an alert in a real scenario, since it could indicate
 /// a bug within the application or a security incident.
+#[cfg(feature = "csv")]
 #[test]
 fn invalid_txid() {
     let expected_output = "
@@ -597,6 +689,7 @@ fn invalid_txid() {
 /// Type mismatch on amount column should raise an error message (e.g string value instead of integer). 
 /// This unexpected operation should be raised as an alert in a real scenario, since it could indicate
 /// a bug within the application or a security incident.
+#[cfg(feature = "csv")]
 #[test]
 fn invalid_amount() {
     let expected_output = "
@@ -619,6 +712,7 @@ fn invalid_amount() {
 
 /// Transactions with same ID should raise an error message, 
 /// since this could indicate a security incident or a critical bug.
+#[cfg(feature = "csv")]
 #[test]
 fn conflicting_transaction() {
     let expected_output = "
@@ -641,6 +735,7 @@ fn conflicting_transaction() {
 
 /// Disputes with unmatching Client ID and Transaction ID should raise an error message, since this
 /// could indicate a critical bug within the application or possible manipulation of data in a security incident.
+#[cfg(feature = "csv")]
 #[test]
 fn divergent_transaction_id() {
     let expected_output = "
@@ -690,6 +785,7 @@ fn divergent_transaction_id() {
 /// `1, 3.5, 0.0, 3.5, false`
 /// 
 /// `2, 0.0, 0.0, 0.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn resolve_missing_dispute() {
     let input = "
@@ -705,8 +801,8 @@ fn resolve_missing_dispute() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 3.5, held: 0.0, total: 3.5, locked: false });
-    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 3.5, held: 0.0, total: 3.5, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -720,7 +816,7 @@ fn resolve_missing_dispute() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -755,6 +851,7 @@ fn resolve_missing_dispute() {
 /// `1, 4.5, 0.0, 4.5, false`
 /// 
 /// `2, 0.0, 0.0, 0.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn chargeback_missing_dispute() {
     let input = "
@@ -770,8 +867,8 @@ fn chargeback_missing_dispute() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 4.5, held: 0.0, total: 4.5, locked: false });
-    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 4.5, held: 0.0, total: 4.5, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -785,7 +882,7 @@ fn chargeback_missing_dispute() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -811,6 +908,7 @@ fn chargeback_missing_dispute() {
 /// `client, available, held, total, locked`
 /// 
 /// `1, 3.0, 0.0, 3.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn withdrawal_missing_clientid() {
     let input = "
@@ -823,7 +921,7 @@ fn withdrawal_missing_clientid() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
     
-    expected_hashmap.insert(1, AccInfo { available: 3.0, held: 0.0, total: 3.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 3.0, held: 0.0, total: 3.0, status: AccountStatus::Active });
 
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -837,7 +935,7 @@ fn withdrawal_missing_clientid() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -864,6 +962,7 @@ fn withdrawal_missing_clientid() {
 /// `client, available, held, total, locked`
 /// 
 /// `1, 2.0, 0.0, 2.0, false`
+#[cfg(feature = "csv")]
 #[test]
 fn dispute_missing_clientid() {
     let input = "
@@ -876,7 +975,7 @@ fn dispute_missing_clientid() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 2.0, held: 0.0, total: 2.0, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 2.0, held: 0.0, total: 2.0, status: AccountStatus::Active });
     
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -890,7 +989,7 @@ fn dispute_missing_clientid() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
@@ -915,6 +1014,7 @@ fn dispute_missing_clientid() {
 /// `client, available, held, total, locked`
 /// 
 /// `1, 3.5, 0.0, 3.5, false`
+#[cfg(feature = "csv")]
 #[test]
 fn withdrawal_without_funds() {
     let input = "
@@ -926,7 +1026,7 @@ fn withdrawal_without_funds() {
     let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
     let is_csv = false;
 
-    expected_hashmap.insert(1, AccInfo { available: 3.5, held: 0.0, total: 3.5, locked: false });
+    expected_hashmap.insert(1, AccInfo { available: 3.5, held: 0.0, total: 3.5, status: AccountStatus::Active });
     
     let output = match csv_read(&input, is_csv){
         Ok(accs) => std::result::Result::Ok(accs),
@@ -940,10 +1040,1963 @@ fn withdrawal_without_funds() {
             Some(acc) => {
                 assert_eq!(acc.total,value.total);
                 assert_eq!(acc.held,value.held);
-                assert_eq!(acc.locked,value.locked);
+                assert_eq!(acc.status,value.status);
                 assert_eq!(acc.available,value.available);
             }
             None => assert!(false),
         }
     }
 }
+
+/// Generated workloads should be reproducible for a given seed and
+/// processed without errors when no malformed rows are requested.
+#[cfg(feature = "csv")]
+#[test]
+fn generated_workload() {
+    let config = workload::WorkloadConfig {
+        clients: 50,
+        transactions: 5000,
+        dispute_ratio: 0.05,
+        ..workload::WorkloadConfig::default()
+    };
+    let mut first = Vec::new();
+    let mut second = Vec::new();
+    workload::generate(&config, &mut first).unwrap();
+    workload::generate(&config, &mut second).unwrap();
+    assert_eq!(first, second);
+
+    let input = String::from_utf8(first).unwrap();
+    assert_eq!(input.lines().count(), 5001);
+    assert!(csv_read(&input, false).is_ok());
+}
+
+/// In lenient mode invalid rows are skipped and reported, while valid rows
+/// are still applied. Strict mode stops at the first invalid row.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 1.0`
+///
+/// `deposit, invalid_client, 2, 2.0`
+///
+/// `deposit, 1, 1, 3.0`
+///
+/// `deposit, 1, 3, 2.0`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 3.0, 0.0, 3.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn lenient_mode() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 1.0
+    deposit, invalid_client, 2, 2.0
+    deposit, 1, 1, 3.0
+    deposit, 1, 3, 2.0";
+
+    let mut engine = Engine::new();
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![Errors::InvalidClient("2".to_string()), Errors::ConflictTransaction("3".to_string())]);
+    assert!(!errors[0].is_security());
+    assert!(errors[1].is_security());
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!(acc.available, 3.0);
+    assert_eq!(acc.held, 0.0);
+    assert_eq!(acc.total, 3.0);
+    assert!(!acc.locked());
+
+    let mut engine = Engine::new();
+    let error = process_csv(&mut engine, input.as_bytes(), Mode::Strict).err().unwrap();
+    assert_eq!(error, Errors::InvalidClient("2".to_string()));
+}
+
+/// Several files are processed in order against the same state: transaction IDs
+/// must be unique across files and errors point to the file raising them.
+/// Directories and patterns expand to their files sorted by name.
+#[cfg(feature = "csv")]
+#[test]
+fn multiple_input_files() {
+    let dir = std::env::temp_dir().join(format!("multiple_input_files_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("day1.csv"), "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 2, 2, 2.0").unwrap();
+    std::fs::write(dir.join("day2.csv"), "type, client, tx, amount\nwithdrawal, 1, 3, 0.5\ndeposit, 2, 2, 3.0").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a transaction file").unwrap();
+
+    let pattern = dir.join("day*.csv").display().to_string();
+    let files = io::expand_inputs(&[pattern]).unwrap();
+    assert_eq!(files, vec![dir.join("day1.csv"), dir.join("day2.csv")]);
+    assert_eq!(io::expand_inputs(&[dir.display().to_string()]).unwrap().len(), 3);
+
+    let mut engine = Engine::new();
+    let error = io::process_files(&mut engine, &files, Mode::Strict).err().unwrap();
+    assert_eq!(error.error, Errors::ConflictTransaction("2".to_string()));
+    assert_eq!(error.to_string(), format!("{}: Conflicting Transaction at line: 2", dir.join("day2.csv").display()));
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!(acc.available, 0.5);
+    assert_eq!(acc.total, 0.5);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Streams are interleaved by timestamp. Rows with the same timestamp follow
+/// the stream order, rows without timestamp inherit the previous one of their
+/// stream and rows going back in time are reported as out of order.
+///
+/// Input (venue_a):
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 1, 1.0, 10`
+///
+/// `withdrawal, 1, 3, 3.0, 30`
+///
+/// `deposit, 1, 5, 1.0, 20`
+///
+/// Input (venue_b):
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 2, 2.0, 10`
+///
+/// `dispute, 1, 2, , 40`
+///
+/// `deposit, 1, 4, 4.0, `
+///
+/// Expected order: tx 1, tx 2, tx 3, tx 5 (out of order, skipped), dispute tx 2, tx 4.
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 2.0, 2.0, 4.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn merged_streams() {
+    let venue_a = "
+    type, client, tx, amount, timestamp
+    deposit, 1, 1, 1.0, 10
+    withdrawal, 1, 3, 3.0, 30
+    deposit, 1, 5, 1.0, 20";
+    let venue_b = "
+    type, client, tx, amount, timestamp
+    deposit, 1, 2, 2.0, 10
+    dispute, 1, 2, , 40
+    deposit, 1, 4, 4.0, ";
+
+    let streams = vec![venue_a.as_bytes(), venue_b.as_bytes()];
+    let order: Vec<(usize, i32)> = io::MergeReader::new(streams).map(|(stream, line, _)| (stream, line)).collect();
+    assert_eq!(order, vec![(0, 1), (1, 1), (0, 2), (0, 3), (1, 2), (1, 3)]);
+
+    let inputs = vec![("venue_a".to_string(), venue_a.as_bytes()), ("venue_b".to_string(), venue_b.as_bytes())];
+    let mut engine = Engine::new();
+    let errors = io::process_merged(&mut engine, inputs, Mode::Lenient).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "venue_a: Out of order Timestamp at line: 3");
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!(acc.available, 2.0);
+    assert_eq!(acc.held, 2.0);
+    assert_eq!(acc.total, 4.0);
+
+    //Unreadable files are skipped in lenient mode, like unmerged files
+    let dir = std::env::temp_dir().join(format!("merged_streams_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("venue_a.csv"), venue_a).unwrap();
+    std::fs::write(dir.join("venue_b.csv"), venue_b).unwrap();
+    let files = vec![dir.join("venue_a.csv"), dir.join("missing.csv"), dir.join("venue_b.csv")];
+    let mut engine = Engine::new();
+    let errors = io::merge_files(&mut engine, &files, Mode::Lenient).unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].origin, dir.join("missing.csv").display().to_string());
+    assert!(matches!(errors[0].error, Errors::InvalidInput(_)));
+    assert_eq!(errors[1].error, Errors::OutOfOrder("3".to_string()));
+    assert_eq!(engine.account(1, DEFAULT_ASSET).unwrap().total, 4.0);
+
+    let mut engine = Engine::new();
+    let error = io::merge_files(&mut engine, &files, Mode::Strict).err().unwrap();
+    assert_eq!(error.origin, dir.join("missing.csv").display().to_string());
+    assert!(engine.account(1, DEFAULT_ASSET).is_none());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Gzip and zstd inputs are decompressed on the fly, detected either by
+/// their magic bytes or by their `.gz`/`.zst` extension.
+#[cfg(feature = "csv")]
+#[test]
+fn compressed_input() {
+    use std::io::Write;
+
+    let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 2, 2, 2.0\nwithdrawal, 1, 3, 0.5";
+    let dir = std::env::temp_dir().join(format!("compressed_input_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(input.as_bytes()).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let zstd = zstd::encode_all(input.as_bytes(), 0).unwrap();
+    std::fs::write(dir.join("day1.csv.gz"), &gzip).unwrap();
+    std::fs::write(dir.join("day1.csv.zst"), &zstd).unwrap();
+    std::fs::write(dir.join("day1_gzip.csv"), &gzip).unwrap();
+
+    assert_eq!(io::Compression::detect(Path::new("day1.csv.zst"), b""), io::Compression::Zstd);
+    assert_eq!(io::Compression::detect(Path::new("day1.csv"), &gzip), io::Compression::Gzip);
+    assert_eq!(io::Compression::detect(Path::new("day1.csv"), input.as_bytes()), io::Compression::None);
+
+    for file in ["day1.csv.gz", "day1.csv.zst", "day1_gzip.csv"] {
+        let accounts = csv_read(dir.join(file).to_str().unwrap(), true).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts.get(&1).unwrap().available, 0.5);
+        assert_eq!(accounts.get(&2).unwrap().total, 2.0);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Balances are kept per client and asset. Rows without asset apply to the
+/// default asset and disputes apply to the asset of the original transaction.
+///
+/// Input:
+///
+/// `type, client, tx, amount, asset`
+///
+/// `deposit, 1, 1, 1.0, `
+///
+/// `deposit, 1, 2, 2.0, BTC`
+///
+/// `deposit, 1, 3, 5.0, EUR`
+///
+/// `withdrawal, 1, 4, 3.0, BTC`
+///
+/// `dispute, 1, 3, , `
+///
+/// `chargeback, 1, 3, , `
+///
+/// Expected:
+///
+/// `client, asset, available, held, total, locked`
+///
+/// `1, default, 1.0, 0.0, 1.0, false`
+///
+/// `1, BTC, 2.0, 0.0, 2.0, false`
+///
+/// `1, EUR, 0.0, 0.0, 0.0, true`
+#[cfg(feature = "csv")]
+#[test]
+fn multi_asset_accounts() {
+    let input = "
+    type, client, tx, amount, asset
+    deposit, 1, 1, 1.0,
+    deposit, 1, 2, 2.0, BTC
+    deposit, 1, 3, 5.0, EUR
+    withdrawal, 1, 4, 3.0, BTC
+    dispute, 1, 3, ,
+    chargeback, 1, 3, , ";
+
+    let mut engine = Engine::new();
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    assert_eq!(engine.accounts().len(), 3);
+
+    let default = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((default.available, default.held, default.total, default.locked()), (1.0, 0.0, 1.0, false));
+    let btc = engine.account(1, "BTC").unwrap();
+    assert_eq!((btc.available, btc.held, btc.total, btc.locked()), (2.0, 0.0, 2.0, false));
+    let eur = engine.account(1, "EUR").unwrap();
+    assert_eq!((eur.available, eur.held, eur.total, eur.locked()), (0.0, 0.0, 0.0, true));
+
+    let output = fmt_asset_output(engine.into_accounts(), false);
+    assert!(output.starts_with("client, asset, available, held, total, locked"));
+    assert!(output.contains("\n1, BTC, 2, 0, 2, false"));
+    assert_eq!(csv_read(input, false).unwrap().len(), 1);
+}
+
+/// Transfers move funds between two clients in one step. A transfer is
+/// skipped when the sender has no funds or when either account is locked.
+///
+/// Input:
+///
+/// `type, client, tx, amount, to`
+///
+/// `deposit, 1, 1, 5.0, `
+///
+/// `deposit, 3, 2, 1.0, `
+///
+/// `transfer, 1, 3, 2.0, 2`
+///
+/// `transfer, 2, 4, 3.0, 1`
+///
+/// `dispute, 3, 2, , `
+///
+/// `chargeback, 3, 2, , `
+///
+/// `transfer, 1, 5, 1.0, 3`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 3.0, 0.0, 3.0, false`
+///
+/// `2, 2.0, 0.0, 2.0, false`
+///
+/// `3, 0.0, 0.0, 0.0, true`
+#[cfg(feature = "csv")]
+#[test]
+fn successful_transfer() {
+    let input = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    deposit, 3, 2, 1.0,
+    transfer, 1, 3, 2.0, 2
+    transfer, 2, 4, 3.0, 1
+    dispute, 3, 2, ,
+    chargeback, 3, 2, ,
+    transfer, 1, 5, 1.0, 3";
+
+    let mut expected_hashmap: HashMap<u16,AccInfo> = HashMap::new();
+    expected_hashmap.insert(1, AccInfo { available: 3.0, held: 0.0, total: 3.0, status: AccountStatus::Active });
+    expected_hashmap.insert(2, AccInfo { available: 2.0, held: 0.0, total: 2.0, status: AccountStatus::Active });
+    expected_hashmap.insert(3, AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::charged_back(6, 2) });
+
+    let result = csv_read(input, false).unwrap();
+    assert_eq!(result.len(), 3);
+    for (key, value) in result {
+        let acc = expected_hashmap.get(&key).unwrap();
+        assert_eq!(acc.total,value.total);
+        assert_eq!(acc.held,value.held);
+        assert_eq!(acc.status,value.status);
+        assert_eq!(acc.available,value.available);
+    }
+
+    let invalid = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    transfer, 1, 2, 2.0, 1";
+    assert_eq!(csv_read(invalid, false).err().unwrap(), "Invalid Recipient at line: 2");
+}
+
+/// Transfers of a negative or zero amount are invalid. A negative transfer
+/// would otherwise credit the sender and debit the recipient.
+///
+/// Input:
+///
+/// `type, client, tx, amount, to`
+///
+/// `deposit, 1, 1, 5.0, `
+///
+/// `deposit, 3, 2, 1.0, `
+///
+/// `transfer, 1, 3, -50.0, 3`
+///
+/// `transfer, 1, 4, 0.0, 3`
+///
+/// Expected: `Invalid Amount` at lines 3 and 4, balances unchanged
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 5.0, 0.0, 5.0, false`
+///
+/// `3, 1.0, 0.0, 1.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn invalid_transfer_amount() {
+    let input = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    deposit, 3, 2, 1.0,
+    transfer, 1, 3, -50.0, 3
+    transfer, 1, 4, 0.0, 3";
+
+    let mut engine = Engine::new();
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![Errors::InvalidAmount("3".to_string()), Errors::InvalidAmount("4".to_string())]);
+    let sender = engine.account(1, DEFAULT_ASSET).unwrap();
+    let recipient = engine.account(3, DEFAULT_ASSET).unwrap();
+    assert_eq!((sender.available, sender.held, sender.total), (5.0, 0.0, 5.0));
+    assert_eq!((recipient.available, recipient.held, recipient.total), (1.0, 0.0, 1.0));
+    assert!(engine.transaction(3).is_none());
+
+    let negative = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    transfer, 1, 2, -1.0, 2";
+    assert_eq!(csv_read(negative, false).err().unwrap(), "Invalid Amount at line: 2");
+}
+
+/// Disputes on a transfer are raised by the sender and hold the funds on the
+/// recipient account. A resolve releases them, while a chargeback returns them
+/// to the sender and locks the recipient account.
+///
+/// Input:
+///
+/// `type, client, tx, amount, to`
+///
+/// `deposit, 1, 1, 5.0, `
+///
+/// `transfer, 1, 2, 2.0, 2`
+///
+/// `dispute, 1, 2, , `
+///
+/// Expected after dispute:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 3.0, 0.0, 3.0, false`
+///
+/// `2, 0.0, 2.0, 2.0, false`
+///
+/// Expected after `chargeback, 1, 2, , `:
+///
+/// `1, 5.0, 0.0, 5.0, false`
+///
+/// `2, 0.0, 0.0, 0.0, true`
+#[cfg(feature = "csv")]
+#[test]
+fn transfer_dispute_and_chargeback() {
+    let input = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    transfer, 1, 2, 2.0, 2
+    dispute, 1, 2, , ";
+
+    let result = csv_read(input, false).unwrap();
+    let sender = result.get(&1).unwrap();
+    let recipient = result.get(&2).unwrap();
+    assert_eq!((sender.available, sender.held, sender.total), (3.0, 0.0, 3.0));
+    assert_eq!((recipient.available, recipient.held, recipient.total), (0.0, 2.0, 2.0));
+
+    let input = format!("{}\n    chargeback, 1, 2, , ", input);
+    let result = csv_read(&input, false).unwrap();
+    let sender = result.get(&1).unwrap();
+    let recipient = result.get(&2).unwrap();
+    assert_eq!((sender.available, sender.held, sender.total, sender.locked()), (5.0, 0.0, 5.0, false));
+    assert_eq!((recipient.available, recipient.held, recipient.total, recipient.locked()), (0.0, 0.0, 0.0, true));
+
+    let divergent = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    transfer, 1, 2, 2.0, 2
+    dispute, 2, 2, , ";
+    assert_eq!(csv_read(divergent, false).err().unwrap(), "Divergent Transaction and Client ID at line: 3");
+}
+
+/// Fees are taken from deposits, debited on top of withdrawals and credited
+/// to the house account. Clients of a tier use the tier rules, each fee is
+/// recorded as an audit entry.
+///
+/// Fee schedule: house account 0, deposits 1% (min 0.5), withdrawals 0.25 flat,
+/// tier "vip" (client 2) without deposit fees.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 100.0`
+///
+/// `deposit, 1, 2, 10.0`
+///
+/// `deposit, 2, 3, 10.0`
+///
+/// `withdrawal, 2, 4, 5.0`
+///
+/// `withdrawal, 1, 5, 108.5`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `0, 1.75, 0.0, 1.75, false`
+///
+/// `1, 108.5, 0.0, 108.5, false`
+///
+/// `2, 4.75, 0.0, 4.75, false`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn deposit_and_withdrawal_fees() {
+    let config = config::Config::from_json(r#"{
+        "fees": {
+            "house_account": 0,
+            "deposit": { "percentage": 1.0, "min": 0.5 },
+            "withdrawal": { "flat": 0.25 },
+            "tiers": [ { "name": "vip", "clients": [2], "deposit": {} } ]
+        }
+    }"#).unwrap();
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 100.0
+    deposit, 1, 2, 10.0
+    deposit, 2, 3, 10.0
+    withdrawal, 2, 4, 5.0
+    withdrawal, 1, 5, 108.5";
+
+    let mut engine = Engine::with_config(config);
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let house = engine.account(0, DEFAULT_ASSET).unwrap();
+    assert_eq!((house.available, house.total), (1.75, 1.75));
+    let client = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((client.available, client.total), (108.5, 108.5));
+    let vip = engine.account(2, DEFAULT_ASSET).unwrap();
+    assert_eq!((vip.available, vip.total), (4.75, 4.75));
+
+    let audit = engine.drain_audit();
+    let fees: Vec<(u32, f32)> = audit.iter().filter_map(|entry| match entry {
+        audit::AuditEntry::Fee { tx, amount, .. } => Some((*tx, *amount)),
+        _ => None,
+    }).collect();
+    assert_eq!(fees, vec![(1, 1.0), (2, 0.5), (4, 0.25)]);
+    assert_eq!(audit[2].to_json(), r#"{"kind":"fee","line":4,"tx":4,"client":2,"asset":"default","operation":"withdrawal","amount":0.25,"house_account":0}"#);
+    assert!(engine.drain_audit().is_empty());
+}
+
+/// Disputes of a deposit hold the credited amount, net of the fee, and a
+/// chargeback reverses that amount while the house account keeps the fee.
+///
+/// Fee schedule: house account 0, deposits 1.0 flat.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0`
+///
+/// `withdrawal, 1, 2, 9.0`
+///
+/// `dispute, 1, 1, ,`
+///
+/// `chargeback, 1, 1, ,`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `0, 1.0, 0.0, 1.0, false`
+///
+/// `1, -9.0, 0.0, -9.0, true`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn disputed_deposit_fees() {
+    let config = config::Config::from_json(r#"{
+        "fees": { "house_account": 0, "deposit": { "flat": 1.0 } }
+    }"#).unwrap();
+    let mut engine = Engine::with_config(config);
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    withdrawal, 1, 2, 9.0
+    dispute, 1, 1, ,";
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    let client = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((client.available, client.held, client.total), (-9.0, 9.0, 0.0));
+    assert_eq!(engine.transaction(1).unwrap().disputed(), 9.0);
+
+    let input = "
+    type, client, tx, amount
+    chargeback, 1, 1, ,";
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    let client = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((client.available, client.held, client.total, client.locked()), (-9.0, 0.0, -9.0, true));
+    let house = engine.account(0, DEFAULT_ASSET).unwrap();
+    assert_eq!((house.available, house.total), (1.0, 1.0));
+}
+
+/// Withdrawals violating the configured limits are rejected and reported
+/// with their reason. Clients with their own limits replace the default ones.
+///
+/// Limits: max 50 per withdrawal, 100 per day, 80 and 2 withdrawals per hour.
+/// Client 7 has no limits.
+///
+/// Input:
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 1, 500.0, 0`
+///
+/// `withdrawal, 1, 2, 60.0, 10` (above max)
+///
+/// `withdrawal, 1, 3, 40.0, 20`
+///
+/// `withdrawal, 1, 4, 45.0, 30` (window amount)
+///
+/// `withdrawal, 1, 5, 30.0, 40`
+///
+/// `withdrawal, 1, 6, 5.0, 50` (window count)
+///
+/// `withdrawal, 1, 7, 35.0, 4000` (daily amount)
+///
+/// `withdrawal, 1, 8, 35.0, 86400`
+///
+/// `deposit, 7, 9, 500.0, 86400`
+///
+/// `withdrawal, 7, 10, 400.0, `
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 395.0, 0.0, 395.0, false`
+///
+/// `7, 100.0, 0.0, 100.0, false`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn withdrawal_limits() {
+    let config = config::Config::from_json(r#"{
+        "limits": {
+            "default": { "max_amount": 50.0, "daily_amount": 100.0, "window_seconds": 3600, "window_amount": 80.0, "window_count": 2 },
+            "clients": { "7": {} }
+        }
+    }"#).unwrap();
+    let input = "
+    type, client, tx, amount, timestamp
+    deposit, 1, 1, 500.0, 0
+    withdrawal, 1, 2, 60.0, 10
+    withdrawal, 1, 3, 40.0, 20
+    withdrawal, 1, 4, 45.0, 30
+    withdrawal, 1, 5, 30.0, 40
+    withdrawal, 1, 6, 5.0, 50
+    withdrawal, 1, 7, 35.0, 4000
+    withdrawal, 1, 8, 35.0, 86400
+    deposit, 7, 9, 500.0, 86400
+    withdrawal, 7, 10, 400.0, ";
+
+    let mut engine = Engine::with_config(config);
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.total), (395.0, 395.0));
+    let acc = engine.account(7, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.total), (100.0, 100.0));
+
+    let rejections = engine.drain_rejections();
+    let reasons: Vec<(u32, audit::RejectReason)> = rejections.iter().map(|r| (r.tx, r.reason)).collect();
+    assert_eq!(reasons, vec![
+        (2, audit::RejectReason::WithdrawalAboveMax),
+        (4, audit::RejectReason::WindowAmountExceeded),
+        (6, audit::RejectReason::WindowCountExceeded),
+        (7, audit::RejectReason::DailyLimitExceeded),
+    ]);
+    assert_eq!(rejections[3].to_string(), "Daily withdrawal limit exceeded for tx 7 at line: 7");
+    assert_eq!(rejections[0].to_json(), r#"{"line":2,"tx":2,"client":1,"reason":"withdrawal_above_max"}"#);
+}
+
+/// Withdrawal windows of timestamps close to `u64::MAX` end at `u64::MAX`
+/// instead of overflowing.
+///
+/// Limits: 1 withdrawal per hour.
+///
+/// Input:
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 1, 10.0, 18446744073709551000`
+///
+/// `withdrawal, 1, 2, 1.0, 18446744073709551000`
+///
+/// `withdrawal, 1, 3, 1.0, 18446744073709551100` (window count)
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 9.0, 0.0, 9.0, false`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn withdrawal_limits_max_timestamp() {
+    let config = config::Config::from_json(r#"{ "limits": { "default": { "window_seconds": 3600, "window_count": 1 } } }"#).unwrap();
+    let input = "
+    type, client, tx, amount, timestamp
+    deposit, 1, 1, 10.0, 18446744073709551000
+    withdrawal, 1, 2, 1.0, 18446744073709551000
+    withdrawal, 1, 3, 1.0, 18446744073709551100";
+
+    let mut engine = Engine::with_config(config);
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.total), (9.0, 9.0));
+    let reasons: Vec<(u32, audit::RejectReason)> = engine.drain_rejections().iter().map(|r| (r.tx, r.reason)).collect();
+    assert_eq!(reasons, vec![(3, audit::RejectReason::WindowCountExceeded)]);
+}
+
+/// Window limits without a window are rejected when the configuration is loaded,
+/// for the default limits and the limits of a client.
+#[cfg(feature = "json")]
+#[test]
+fn window_limits_config() {
+    let error = config::Config::from_json(r#"{ "limits": { "default": { "window_count": 2 } } }"#).unwrap_err();
+    assert!(error.to_string().starts_with("limits.default: window_amount and window_count require a positive window_seconds"));
+    let error = config::Config::from_json(r#"{ "limits": { "clients": { "7": { "window_amount": 80.0, "window_seconds": 0 } } } }"#).unwrap_err();
+    assert!(error.to_string().starts_with("limits.clients.7: "));
+    assert!(config::Config::from_json(r#"{ "limits": { "default": { "window_seconds": 3600, "window_count": 2 } } }"#).is_ok());
+    assert!(config::Config::from_json(r#"{ "limits": { "default": { "daily_amount": 100.0 } } }"#).is_ok());
+}
+
+/// Configured rules reject or hold disputes, decisions are audited and held
+/// rows can be applied once reviewed.
+///
+/// Rules: max 0.5 disputes per deposit (hold), max 2 disputes (reject).
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0` (x4, tx 1 to 4)
+///
+/// `dispute, 1, 1,`
+///
+/// `dispute, 1, 2,`
+///
+/// `resolve, 1, 1,`
+///
+/// `deposit, 2, 5, 5.0`
+///
+/// `dispute, 2, 5,` (held, 1 dispute per deposit)
+///
+/// `dispute, 1, 3,` (rejected, 2 disputes already)
+///
+/// Expected, once the held dispute is applied:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 30.0, 10.0, 40.0, false`
+///
+/// `2, 0.0, 5.0, 5.0, false`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn dispute_rules() {
+    let config = config::Config::from_json(r#"{
+        "rules": [
+            { "rule": "dispute_ratio", "max_ratio": 0.5, "action": "hold" },
+            { "rule": "max_disputes", "max": 2, "action": "reject" }
+        ]
+    }"#).unwrap();
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    deposit, 1, 2, 10.0
+    deposit, 1, 3, 10.0
+    deposit, 1, 4, 10.0
+    dispute, 1, 1,
+    dispute, 1, 2,
+    resolve, 1, 1,
+    deposit, 2, 5, 5.0
+    dispute, 2, 5,
+    dispute, 1, 3, ";
+
+    let mut engine = Engine::with_config(config);
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    assert_eq!(engine.client_stats(1).disputes, 2);
+
+    let rejections = engine.drain_rejections();
+    assert_eq!(rejections.len(), 1);
+    assert_eq!((rejections[0].tx, rejections[0].reason), (3, audit::RejectReason::RuleRejected));
+    let audit = engine.drain_audit();
+    assert_eq!(audit.len(), 2);
+    assert_eq!(audit[1].to_json(), r#"{"kind":"rule","line":10,"tx":3,"client":1,"rule":"max_disputes","action":"reject","reason":"client already opened 2 disputes"}"#);
+
+    let mut held = engine.take_held();
+    let summary: Vec<(i32, u16, &str)> = held.iter().map(|h| (h.line, h.row.client().unwrap(), h.rule.as_str())).collect();
+    assert_eq!(summary, vec![(9, 2, "dispute_ratio")]);
+    assert!(engine.take_held().is_empty());
+    engine.apply_reviewed(held.remove(0)).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (30.0, 10.0, 40.0));
+    let acc = engine.account(2, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (0.0, 5.0, 5.0));
+}
+
+/// Accounts are frozen once the client reaches an auto lock trigger,
+/// with the reason stored on the account and shown in the output.
+///
+/// Triggers: more than 1 open dispute, more than 50% of deposits disputed,
+/// more than 1 divergent client ID attempt.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0` (x3, tx 1 to 3)
+///
+/// `dispute, 1, 1,`
+///
+/// `dispute, 1, 2,` (2 open disputes)
+///
+/// `deposit, 2, 4, 10.0`
+///
+/// `deposit, 2, 5, 5.0`
+///
+/// `dispute, 2, 4,` (66.67% disputed)
+///
+/// `deposit, 3, 6, 10.0`
+///
+/// `dispute, 4, 6,`
+///
+/// `dispute, 5, 6,` (2 divergent attempts against client 3)
+///
+/// Expected:
+///
+/// `client, available, held, total, locked, status, reason, line, tx`
+///
+/// `1, 10.0, 20.0, 30.0, true, frozen, 2 open disputes above 1, 5, 2`
+///
+/// `2, 5.0, 10.0, 15.0, true, frozen, disputed 66.67% of deposits above 50%, 8, 4`
+///
+/// `3, 10.0, 0.0, 10.0, true, frozen, 2 divergent client ID attempts above 1, 11, 6`
+///
+/// Disputes of a transfer freeze the sender raising them, while the funds are
+/// held on the recipient account, and the lock event names the sender
+/// (trigger: more than 0 open disputes):
+///
+/// `deposit, 1, 1, 5.0`
+///
+/// `transfer, 1, 2, 2.0, 2`
+///
+/// `dispute, 1, 2, ,`
+///
+/// Expected:
+///
+/// `1, 3.0, 0.0, 3.0, true, frozen, 1 open disputes above 0, 3, 2`
+///
+/// `2, 0.0, 2.0, 2.0, false, active, , , `
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn auto_lock() {
+    let config = config::Config::from_json(r#"{
+        "auto_lock": { "open_disputes": 1, "disputed_percentage": 50.0, "divergent_client_attempts": 1 }
+    }"#).unwrap();
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    deposit, 1, 2, 10.0
+    deposit, 1, 3, 10.0
+    dispute, 1, 1,
+    dispute, 1, 2,
+    deposit, 2, 4, 10.0
+    deposit, 2, 5, 5.0
+    dispute, 2, 4,
+    deposit, 3, 6, 10.0
+    dispute, 4, 6,
+    dispute, 5, 6, ";
+
+    let mut engine = Engine::with_config(config);
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![
+        Errors::SecurityErrDivergentClientId("10".to_string()),
+        Errors::SecurityErrDivergentClientId("11".to_string()),
+    ]);
+
+    let expected = [
+        (1, (10.0, 20.0, 30.0), AccountStatus::auto_locked("2 open disputes above 1", 5, 2)),
+        (2, (5.0, 10.0, 15.0), AccountStatus::auto_locked("disputed 66.67% of deposits above 50%", 8, 4)),
+        (3, (10.0, 0.0, 10.0), AccountStatus::auto_locked("2 divergent client ID attempts above 1", 11, 6)),
+    ];
+    for (client, balances, status) in expected {
+        let acc = engine.account(client, DEFAULT_ASSET).unwrap();
+        assert_eq!((acc.available, acc.held, acc.total), balances);
+        assert_eq!(acc.status, status);
+    }
+
+    let audit = engine.drain_audit();
+    assert_eq!(audit.len(), 3);
+    assert_eq!(audit[0].to_json(), r#"{"kind":"lock","line":5,"tx":2,"client":1,"asset":"default","reason":"2 open disputes above 1"}"#);
+
+    let output = fmt_asset_output(engine.into_accounts(), true);
+    assert!(output.starts_with("client, available, held, total, locked, status, reason, line, tx\n"));
+    assert!(output.contains("\n3, 10, 0, 10, true, frozen, 2 divergent client ID attempts above 1, 11, 6"));
+
+    let config = config::Config::from_json(r#"{ "auto_lock": { "open_disputes": 0 } }"#).unwrap();
+    let input = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    transfer, 1, 2, 2.0, 2
+    dispute, 1, 2, ,";
+    let mut engine = Engine::with_config(config);
+    let (subscriber, events) = events::channel();
+    engine.subscribe(Box::new(subscriber));
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    let locks: Vec<(u16, String)> = events.try_iter().filter_map(|event| match event {
+        events::Event::AccountLocked { client, asset, .. } => Some((client, asset)),
+        _ => None,
+    }).collect();
+    assert_eq!(locks, vec![(1, DEFAULT_ASSET.to_string())]);
+    let sender = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((sender.available, sender.held, sender.total), (3.0, 0.0, 3.0));
+    assert_eq!(sender.status, AccountStatus::auto_locked("1 open disputes above 0", 3, 2));
+    let recipient = engine.account(2, DEFAULT_ASSET).unwrap();
+    assert_eq!((recipient.available, recipient.held, recipient.total), (0.0, 2.0, 2.0));
+    assert_eq!(recipient.status, AccountStatus::Active);
+    assert_eq!(engine.drain_audit()[0].to_json(), r#"{"kind":"lock","line":3,"tx":2,"client":1,"asset":"default","reason":"1 open disputes above 0"}"#);
+}
+
+/// Disputes opened before an auto lock can still be resolved or charged back,
+/// while new disputes on the locked account are skipped. The account keeps
+/// its auto lock status after the chargeback.
+///
+/// Auto lock: max 1 open dispute.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0` (x3, tx 1 to 3)
+///
+/// `dispute, 1, 1,`
+///
+/// `dispute, 1, 2,` (locks the account)
+///
+/// `dispute, 1, 3,` (skipped)
+///
+/// `resolve, 1, 1,`
+///
+/// `chargeback, 1, 2,`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 20.0, 0.0, 20.0, true`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn settle_after_auto_lock() {
+    let config = config::Config::from_json(r#"{ "auto_lock": { "open_disputes": 1 } }"#).unwrap();
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    deposit, 1, 2, 10.0
+    deposit, 1, 3, 10.0
+    dispute, 1, 1,
+    dispute, 1, 2,
+    dispute, 1, 3,
+    resolve, 1, 1,
+    chargeback, 1, 2,";
+
+    let mut engine = Engine::with_config(config);
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (20.0, 0.0, 20.0));
+    assert_eq!(acc.status, AccountStatus::auto_locked("2 open disputes above 1", 5, 2));
+    assert!(!engine.transaction(3).unwrap().in_dispute());
+    let stats = engine.client_stats(1);
+    assert_eq!((stats.open_disputes, stats.chargebacks), (0, 1));
+}
+
+/// Administrators can freeze and close accounts, the status of locked
+/// accounts is optionally added to the output.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0`
+///
+/// `deposit, 2, 2, 5.0`
+///
+/// `deposit, 3, 3, 1.0`
+///
+/// `withdrawal, 3, 4, 1.0`
+///
+/// Then client 1 is frozen, client 3 closed and client 2 cannot be closed (funds).
+///
+/// `deposit, 1, 5, 10.0` (skipped, frozen)
+///
+/// `deposit, 3, 6, 10.0` (skipped, closed)
+///
+/// Expected:
+///
+/// `client, available, held, total, locked, status, reason, line, tx`
+///
+/// `1, 10.0, 0.0, 10.0, true, frozen, "admin freeze, KYC", , `
+///
+/// `2, 5.0, 0.0, 5.0, false, active, , , `
+///
+/// `3, 0.0, 0.0, 0.0, true, closed, , , `
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn account_status() {
+    let mut engine = Engine::new();
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    deposit, 2, 2, 5.0
+    deposit, 3, 3, 1.0
+    withdrawal, 3, 4, 1.0";
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    assert!(engine.freeze(1, DEFAULT_ASSET, "admin freeze, KYC"));
+    assert!(!engine.freeze(4, DEFAULT_ASSET, "admin freeze"));
+    assert!(!engine.close(2, DEFAULT_ASSET));
+    assert!(engine.close(3, DEFAULT_ASSET));
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 5, 10.0
+    deposit, 3, 6, 10.0";
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!(acc.total, 10.0);
+    assert_eq!(acc.status, AccountStatus::Frozen { cause: FreezeCause::Manual, reason: "admin freeze, KYC".to_string(), line: None, tx: None });
+    assert_eq!(engine.account(3, DEFAULT_ASSET).unwrap().status, AccountStatus::Closed);
+
+    let mut accounts = HashMap::new();
+    accounts.insert((1, DEFAULT_ASSET.to_string()), AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::charged_back(7, 42) });
+    assert_eq!(fmt_asset_output(accounts, true), "client, available, held, total, locked, status, reason, line, tx\n1, 0, 0, 0, true, frozen, chargeback, 7, 42");
+    let mut accounts = HashMap::new();
+    accounts.insert((1, DEFAULT_ASSET.to_string()), AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::charged_back(7, 42) });
+    assert_eq!(fmt_output_json(accounts, true).split_whitespace().collect::<String>(),
+        r#"[{"client":1,"available":0.0,"held":0.0,"total":0.0,"locked":true,"status":"frozen","cause":"chargeback","reason":"chargeback","line":7,"tx":42}]"#);
+
+    let output = fmt_asset_output(engine.into_accounts(), true);
+    assert!(output.contains("\n1, 10, 0, 10, true, frozen, \"admin freeze, KYC\", , "));
+    assert!(output.contains("\n2, 5, 0, 5, false, active, , , "));
+    assert!(output.contains("\n3, 0, 0, 0, true, closed, , , "));
+}
+
+/// Disputes of transactions older than the window configured for their type
+/// are rejected and reported, other disputes are applied.
+///
+/// Windows: deposits 120 days, withdrawals 2 rows back.
+///
+/// Input:
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 1, 10.0, 0`
+///
+/// `deposit, 1, 2, 10.0, 200`
+///
+/// `withdrawal, 1, 3, 5.0, 200`
+///
+/// `dispute, 1, 2, , 10368200` (120 days after tx 2)
+///
+/// `dispute, 1, 1, , 10368201` (late)
+///
+/// `dispute, 1, 3, ,` (3 rows back, late)
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 5.0, 10.0, 15.0, false`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn dispute_windows() {
+    let config = config::Config::from_json(r#"{
+        "dispute_windows": {
+            "deposit": { "max_age_seconds": 10368000 },
+            "withdrawal": { "max_transactions": 2 }
+        }
+    }"#).unwrap();
+    let input = "
+    type, client, tx, amount, timestamp
+    deposit, 1, 1, 10.0, 0
+    deposit, 1, 2, 10.0, 200
+    withdrawal, 1, 3, 5.0, 200
+    dispute, 1, 2, , 10368200
+    dispute, 1, 1, , 10368201
+    dispute, 1, 3, ,";
+
+    let mut engine = Engine::with_config(config);
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (5.0, 10.0, 15.0));
+    let rejections = engine.drain_rejections();
+    let reasons: Vec<(i32, u32, audit::RejectReason)> = rejections.iter().map(|r| (r.line, r.tx, r.reason)).collect();
+    assert_eq!(reasons, vec![
+        (5, 1, audit::RejectReason::DisputeWindowExpired),
+        (6, 3, audit::RejectReason::DisputeWindowExpired),
+    ]);
+    assert_eq!(rejections[0].to_json(), r#"{"line":5,"tx":1,"client":1,"reason":"dispute_window_expired"}"#);
+}
+
+/// Disputes, resolves and chargebacks with an amount only move that part of
+/// the transaction, until the original amount is exhausted. The account frozen
+/// by a partial chargeback still settles the rest of the disputed amount.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 100.0`
+///
+/// `dispute, 1, 1, 30.0`
+///
+/// `dispute, 1, 1, 50.0`
+///
+/// `dispute, 1, 1, 30.0` (rejected, 20.0 left)
+///
+/// `dispute, 1, 1,` (remaining 20.0)
+///
+/// `resolve, 1, 1, 40.0`
+///
+/// `chargeback, 1, 1, 70.0` (rejected, 60.0 disputed)
+///
+/// `chargeback, 1, 1, 25.0`
+///
+/// `dispute, 1, 1, -5.0` (invalid amount)
+///
+/// `resolve, 1, 1, 15.0` (frozen by the chargeback of tx 1)
+///
+/// `chargeback, 1, 1,` (remaining 20.0)
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 55.0, 0.0, 55.0, true`
+#[cfg(feature = "csv")]
+#[test]
+fn partial_disputes() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 100.0
+    dispute, 1, 1, 30.0
+    dispute, 1, 1, 50.0
+    dispute, 1, 1, 30.0
+    dispute, 1, 1,
+    resolve, 1, 1, 40.0
+    chargeback, 1, 1, 70.0
+    chargeback, 1, 1, 25.0
+    dispute, 1, 1, -5.0";
+
+    let mut engine = Engine::new();
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![Errors::InvalidAmount("9".to_string())]);
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total, acc.locked()), (40.0, 35.0, 75.0, true));
+    let stats = engine.client_stats(1);
+    assert_eq!((stats.disputes, stats.open_disputes, stats.chargebacks, stats.disputed), (3, 1, 1, 100.0));
+
+    let input = "
+    type, client, tx, amount
+    resolve, 1, 1, 15.0
+    chargeback, 1, 1,";
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (55.0, 0.0, 55.0));
+    assert_eq!(acc.status, AccountStatus::charged_back(8, 1));
+    assert_eq!(engine.transaction(1).unwrap().disputed(), 0.0);
+    let stats = engine.client_stats(1);
+    assert_eq!((stats.open_disputes, stats.chargebacks), (0, 2));
+
+    let rejections = engine.drain_rejections();
+    let reasons: Vec<(i32, audit::RejectReason)> = rejections.iter().map(|r| (r.line, r.reason)).collect();
+    assert_eq!(reasons, vec![
+        (4, audit::RejectReason::DisputableAmountExceeded),
+        (7, audit::RejectReason::DisputedAmountExceeded),
+    ]);
+}
+
+/// Exact resends of a recorded transaction are idempotent and reported as
+/// duplicates, while reused transaction IDs with a different content remain
+/// conflicts.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0`
+///
+/// `withdrawal, 1, 2, 4.0`
+///
+/// `deposit, 1, 1, 10.0` (duplicate)
+///
+/// `withdrawal, 1, 2, 4.0` (duplicate)
+///
+/// `deposit, 2, 1, 10.0` (conflict)
+///
+/// `withdrawal, 1, 1, 10.0` (conflict)
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 6.0, 0.0, 6.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn duplicate_transactions() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    withdrawal, 1, 2, 4.0
+    deposit, 1, 1, 10.0
+    withdrawal, 1, 2, 4.0
+    deposit, 2, 1, 10.0
+    withdrawal, 1, 1, 10.0";
+
+    let mut engine = Engine::new();
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![Errors::ConflictTransaction("5".to_string()), Errors::ConflictTransaction("6".to_string())]);
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (6.0, 0.0, 6.0));
+    assert!(engine.account(2, DEFAULT_ASSET).is_none());
+
+    let rejections = engine.drain_rejections();
+    let reasons: Vec<(i32, u32, audit::RejectReason)> = rejections.iter().map(|r| (r.line, r.tx, r.reason)).collect();
+    assert_eq!(reasons, vec![
+        (3, 1, audit::RejectReason::DuplicateTransaction),
+        (4, 2, audit::RejectReason::DuplicateTransaction),
+    ]);
+    assert_eq!(rejections[0].to_string(), "Duplicate transaction for tx 1 at line: 3");
+
+    let mut engine = Engine::new();
+    let error = process_csv(&mut engine, input.as_bytes(), Mode::Strict).err().unwrap();
+    assert_eq!(error, Errors::ConflictTransaction("5".to_string()));
+}
+
+/// Withdrawals that were never applied (insufficient funds, missing account)
+/// are recorded as failed: disputing them is rejected and moves no funds.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 5.0`
+///
+/// `withdrawal, 1, 2, 10.0` (insufficient funds)
+///
+/// `withdrawal, 2, 3, 1.0` (missing account)
+///
+/// `dispute, 1, 2,`
+///
+/// `dispute, 2, 3,`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 5.0, 0.0, 5.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn dispute_failed_withdrawal() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 5.0
+    withdrawal, 1, 2, 10.0
+    withdrawal, 2, 3, 1.0
+    dispute, 1, 2,
+    dispute, 2, 3,";
+
+    let mut engine = Engine::new();
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (5.0, 0.0, 5.0));
+    assert!(engine.account(2, DEFAULT_ASSET).is_none());
+    let rejections = engine.drain_rejections();
+    let reasons: Vec<(i32, audit::RejectReason)> = rejections.iter().map(|r| (r.line, r.reason)).collect();
+    assert_eq!(reasons, vec![(4, audit::RejectReason::FailedTransaction), (5, audit::RejectReason::FailedTransaction)]);
+    assert_eq!(rejections[0].to_string(), "Transaction was not applied for tx 2 at line: 4");
+}
+
+/// Resolving a failed withdrawal is rejected and moves no funds.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 5.0`
+///
+/// `withdrawal, 1, 2, 10.0` (insufficient funds)
+///
+/// `dispute, 1, 2,`
+///
+/// `resolve, 1, 2,`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 5.0, 0.0, 5.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn resolve_failed_withdrawal() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 5.0
+    withdrawal, 1, 2, 10.0
+    dispute, 1, 2,
+    resolve, 1, 2,";
+
+    let mut engine = Engine::new();
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total), (5.0, 0.0, 5.0));
+    let reasons: Vec<(i32, audit::RejectReason)> = engine.drain_rejections().iter().map(|r| (r.line, r.reason)).collect();
+    assert_eq!(reasons, vec![(3, audit::RejectReason::FailedTransaction), (4, audit::RejectReason::FailedTransaction)]);
+}
+
+/// Charging back a failed withdrawal is rejected: no funds move and
+/// the account is not frozen.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 5.0`
+///
+/// `withdrawal, 1, 2, 10.0` (insufficient funds)
+///
+/// `dispute, 1, 2,`
+///
+/// `chargeback, 1, 2,`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 5.0, 0.0, 5.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn chargeback_failed_withdrawal() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 5.0
+    withdrawal, 1, 2, 10.0
+    dispute, 1, 2,
+    chargeback, 1, 2,";
+
+    let mut engine = Engine::new();
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total, acc.locked()), (5.0, 0.0, 5.0, false));
+    let reasons: Vec<(i32, audit::RejectReason)> = engine.drain_rejections().iter().map(|r| (r.line, r.reason)).collect();
+    assert_eq!(reasons, vec![(3, audit::RejectReason::FailedTransaction), (4, audit::RejectReason::FailedTransaction)]);
+}
+
+/// Resolves and chargebacks of a failed withdrawal raised by another client
+/// are divergent client IDs, counted against the owner of the withdrawal,
+/// rather than failed transactions.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 5.0`
+///
+/// `withdrawal, 1, 2, 10.0` (insufficient funds)
+///
+/// `resolve, 2, 2,`
+///
+/// `chargeback, 2, 2,`
+///
+/// Expected: `Divergent Transaction and Client ID` at lines 3 and 4
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 5.0, 0.0, 5.0, false`
+#[cfg(feature = "csv")]
+#[test]
+fn divergent_failed_withdrawal() {
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 5.0
+    withdrawal, 1, 2, 10.0
+    resolve, 2, 2,
+    chargeback, 2, 2,";
+
+    let mut engine = Engine::new();
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![Errors::SecurityErrDivergentClientId("3".to_string()), Errors::SecurityErrDivergentClientId("4".to_string())]);
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available, acc.held, acc.total, acc.locked()), (5.0, 0.0, 5.0, false));
+    assert!(engine.drain_rejections().is_empty());
+    assert_eq!(engine.client_stats(1).divergent_attempts, 2);
+}
+
+/// Embedding crates apply rows built from any serde format and read the state
+/// through accessors and serializable snapshots.
+///
+/// Input (JSON rows):
+///
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": 2.5}`
+///
+/// `{"type": "deposit", "client": 2, "tx": 2, "amount": 1.0, "asset": "btc"}`
+///
+/// `{"type": "dispute", "client": 1, "tx": 1}`
+///
+/// Expected:
+///
+/// `client, asset, available, held, total, locked`
+///
+/// `1, default, 0.0, 2.5, 2.5, false`
+///
+/// `2, btc, 1.0, 0.0, 1.0, false`
+#[cfg(feature = "json")]
+#[test]
+fn library_api() {
+    let rows = [
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 2.5}"#,
+        r#"{"type": "deposit", "client": 2, "tx": 2, "amount": 1.0, "asset": "btc"}"#,
+        r#"{"type": "dispute", "client": 1, "tx": 1}"#,
+    ];
+    let mut engine = Engine::new();
+    for (index, row) in rows.iter().enumerate() {
+        let row: Input = serde_json::from_str(row).unwrap();
+        engine.apply(row, index as i32 + 1).unwrap();
+    }
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available(), acc.held(), acc.total()), (0.0, 2.5, 2.5));
+    assert_eq!(acc.status(), &AccountStatus::Active);
+    let txs = engine.transaction(1).unwrap();
+    assert_eq!((txs.input().op_type(), txs.status(), txs.disputed(), txs.disputable()), (Some(Operation::Deposit), TxStatus::Applied, 2.5, 0.0));
+
+    let accounts = engine.account_snapshots();
+    assert_eq!(accounts.iter().map(|a| (a.client, a.asset.as_str())).collect::<Vec<_>>(), vec![(1, DEFAULT_ASSET), (2, "btc")]);
+    let json = serde_json::to_string(&accounts[1]).unwrap();
+    assert_eq!(json, r#"{"client":2,"asset":"btc","available":1.0,"held":0.0,"total":1.0,"locked":false,"status":"active"}"#);
+    assert_eq!(serde_json::from_str::<AccountSnapshot>(&json).unwrap(), accounts[1]);
+
+    let transactions = engine.transaction_snapshots();
+    assert_eq!(transactions.len(), 2);
+    let json = serde_json::to_string(&transactions[0]).unwrap();
+    assert_eq!(json, r#"{"tx":1,"client":1,"operation":"deposit","asset":"default","amount":2.5,"to":null,"timestamp":0,"status":"applied","disputed":2.5,"disputable":0.0}"#);
+    assert_eq!(serde_json::from_str::<TransactionSnapshot>(&json).unwrap(), transactions[0]);
+}
+
+/// Subscribers receive typed events once each row is applied, the channel
+/// adapter forwards them to a consumer thread.
+///
+/// Input:
+///
+/// `type, client, tx, amount, to`
+///
+/// `deposit, 1, 1, 5.0,` (account created)
+///
+/// `deposit, 1, 2, 3.0,`
+///
+/// `transfer, 1, 3, 2.0, 2` (account created)
+///
+/// `dispute, 1, 1, ,` (dispute opened)
+///
+/// `chargeback, 1, 1, ,` (chargeback applied, account locked)
+///
+/// Then client 2 is frozen by an administrator (account locked).
+#[cfg(feature = "csv")]
+#[test]
+fn event_subscribers() {
+    let (subscriber, receiver) = events::channel();
+    // Balance changes are covered by the balance_history test
+    let consumer = std::thread::spawn(move || receiver.iter()
+        .filter(|event| !matches!(event, events::Event::BalanceChanged { .. }))
+        .collect::<Vec<events::Event>>());
+
+    let input = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    deposit, 1, 2, 3.0,
+    transfer, 1, 3, 2.0, 2
+    dispute, 1, 1, ,
+    chargeback, 1, 1, ,";
+    let mut engine = Engine::new();
+    engine.subscribe(Box::new(subscriber));
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    assert!(engine.freeze(2, DEFAULT_ASSET, "admin freeze"));
+    drop(engine);
+
+    let asset = DEFAULT_ASSET.to_string();
+    assert_eq!(consumer.join().unwrap(), vec![
+        events::Event::AccountCreated { line: 1, client: 1, asset: asset.clone() },
+        events::Event::AccountCreated { line: 3, client: 2, asset: asset.clone() },
+        events::Event::DisputeOpened { line: 4, tx: 1, client: 1, asset: asset.clone(), amount: 5.0 },
+        events::Event::ChargebackApplied { line: 5, tx: 1, client: 1, asset: asset.clone(), amount: 5.0 },
+        events::Event::AccountLocked { client: 1, asset: asset.clone(), status: AccountStatus::charged_back(5, 1) },
+        events::Event::AccountLocked { client: 2, asset, status: AccountStatus::Frozen { cause: FreezeCause::Manual, reason: "admin freeze".to_string(), line: None, tx: None } },
+    ]);
+}
+
+/// Several producers submit CSV streams to the async engine handle without
+/// blocking, the actor task returns the engine once every handle is dropped.
+///
+/// Input (two streams):
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 2.0`
+///
+/// `withdrawal, 1, 2, 0.5`
+///
+/// and
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 2, 3, 4.0`
+///
+/// `deposit, invalid_client, 4, 1.0`
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 1.5, 0.0, 1.5, false`
+///
+/// `2, 4.0, 0.0, 4.0, false`
+#[cfg(feature = "async")]
+#[test]
+fn async_engine_handle() {
+    use async_engine::{process_stream, EngineHandle};
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let engine = runtime.block_on(async {
+        let (handle, task) = EngineHandle::spawn(Engine::new());
+        let first = handle.clone();
+        let producer = tokio::spawn(async move {
+            process_stream(&first, "type, client, tx, amount\ndeposit, 1, 1, 2.0\n\nwithdrawal, 1, 2, 0.5\n".as_bytes(), Mode::Strict).await
+        });
+        let errors = process_stream(&handle, "type, client, tx, amount\ndeposit, 2, 3, 4.0\ndeposit, invalid_client, 4, 1.0".as_bytes(), Mode::Lenient).await;
+        assert_eq!(errors, Ok(vec![Errors::InvalidClient("2".to_string())]));
+        assert_eq!(producer.await.unwrap(), Ok(vec![]));
+
+        let acc = handle.account(1, DEFAULT_ASSET).await.unwrap();
+        assert_eq!((acc.available, acc.held, acc.total), (1.5, 0.0, 1.5));
+        assert_eq!(handle.accounts().await.len(), 2);
+        drop(handle);
+        task.await.unwrap()
+    });
+    let acc = engine.account(2, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available(), acc.held(), acc.total()), (4.0, 0.0, 4.0));
+}
+
+/// Accounts are compared against expected balances: missing and extra
+/// accounts are reported, amounts outside the tolerance and locked flags.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 2.0`
+///
+/// `deposit, 2, 2, 3.0`
+///
+/// `deposit, 3, 3, 1.0`
+///
+/// Expected accounts:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 2.005, 0.0, 2.005, false`
+///
+/// `2, 2.0, 0.0, 2.0, true`
+///
+/// `4, 1.0, 0.0, 1.0, false`
+///
+/// Expected (tolerance 0.01): client 2 available, total and locked differ,
+/// client 3 is extra and client 4 is missing.
+#[cfg(feature = "csv")]
+#[test]
+fn reconcile_accounts() {
+    use reconcile::{read_expected, reconcile, Difference, Tolerance};
+
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 2.0
+    deposit, 2, 2, 3.0
+    deposit, 3, 3, 1.0";
+    let mut engine = Engine::new();
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+
+    let expected = read_expected("
+    client, available, held, total, locked
+    1, 2.005, 0.0, 2.005, false
+    2, 2.0, 0.0, 2.0, true
+    4, 1.0, 0.0, 1.0, false".as_bytes()).unwrap();
+    let field = |field: &str, expected: &str, actual: &str| Difference::Field {
+        client: 2, asset: DEFAULT_ASSET.to_string(), field: field.to_string(), expected: expected.to_string(), actual: actual.to_string(),
+    };
+    let differences = reconcile(engine.accounts(), &expected, Tolerance { absolute: 0.01, relative: 0.0 });
+    assert_eq!(differences, vec![
+        field("available", "2", "3"),
+        field("total", "2", "3"),
+        field("locked", "true", "false"),
+        Difference::Extra { client: 3, asset: DEFAULT_ASSET.to_string() },
+        Difference::Missing { client: 4, asset: DEFAULT_ASSET.to_string() },
+    ]);
+
+    // Amounts of clients 1 and 2 are within 50% of the expected ones
+    let relative = reconcile(engine.accounts(), &expected, Tolerance { absolute: 0.0, relative: 50.0 });
+    assert_eq!(relative, differences[2..].to_vec());
+    assert_eq!(read_expected("client, available, held, total, locked\n1, x, 0.0, 0.0, false".as_bytes()), Err(Errors::InvalidRecord("1".to_string())));
+}
+
+/// Batches are simulated on an engine restored from a saved state: the
+/// changed accounts and rejections are returned, then the batch is undone
+/// and the engine is left unchanged, also when the batch fails.
+///
+/// State:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 2.0`
+///
+/// `deposit, 2, 2, 3.0`
+///
+/// `dispute, 1, 1,`
+///
+/// Batch:
+///
+/// `type, client, tx, amount`
+///
+/// `chargeback, 1, 1,`
+///
+/// `deposit, 3, 3, 1.0`
+///
+/// `deposit, 2, 2, 3.0`
+///
+/// Expected changes:
+///
+/// `client, asset, available, held, total, locked, available_change, held_change, total_change`
+///
+/// `1, default, 0, 0, 0, true, 0, -2, -2`
+///
+/// `3, default, 1, 0, 1, false, 1, 0, 1`
+///
+/// Expected changes of a batch freezing client 2:
+///
+/// `2, default, 3, 0, 3, true, 0, 0, 0`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn simulate_batch() {
+    let mut engine = Engine::new();
+    process_csv(&mut engine, "type, client, tx, amount\ndeposit, 1, 1, 2.0\ndeposit, 2, 2, 3.0\ndispute, 1, 1,".as_bytes(), Mode::Strict).unwrap();
+    let state: EngineState = serde_json::from_str(&serde_json::to_string(&engine.state()).unwrap()).unwrap();
+    let mut restored = Engine::new();
+    restored.restore(state);
+    assert_eq!(restored.account_snapshots(), engine.account_snapshots());
+    assert_eq!(restored.transaction_snapshots(), engine.transaction_snapshots());
+
+    let batch = "type, client, tx, amount\nchargeback, 1, 1,\ndeposit, 3, 3, 1.0\ndeposit, 2, 2, 3.0";
+    let (simulation, errors) = restored.simulate(|engine| process_csv(engine, batch.as_bytes(), Mode::Lenient)).unwrap();
+    assert_eq!(errors, vec![]);
+    assert_eq!(fmt_simulation(&simulation), "client, asset, available, held, total, locked, available_change, held_change, total_change\n\
+        1, default, 0, 0, 0, true, 0, -2, -2\n\
+        3, default, 1, 0, 1, false, 1, 0, 1");
+    assert_eq!(simulation.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>(), vec![(2, audit::RejectReason::DuplicateTransaction)]);
+    assert_eq!(restored.account_snapshots(), engine.account_snapshots());
+    assert_eq!(restored.transaction_snapshots(), engine.transaction_snapshots());
+    assert_eq!(restored.state().stats, engine.state().stats);
+    assert_eq!(restored.transaction(1).unwrap().disputed(), 2.0);
+    assert!(restored.transaction(3).is_none());
+    assert!(restored.drain_rejections().is_empty());
+    assert_eq!(restored.summary().rows, 0);
+
+    let (simulation, frozen) = restored.simulate(|engine| Ok::<bool, Errors>(engine.freeze(2, DEFAULT_ASSET, "review"))).unwrap();
+    assert!(frozen);
+    assert_eq!(fmt_simulation(&simulation), "client, asset, available, held, total, locked, available_change, held_change, total_change\n\
+        2, default, 3, 0, 3, true, 0, 0, 0");
+    assert!(!restored.account(2, DEFAULT_ASSET).unwrap().locked());
+
+    let strict = restored.simulate(|engine| process_csv(engine, "type, client, tx, amount\ndeposit, 4, 4, 1.0\ndeposit, x, 5, 1.0".as_bytes(), Mode::Strict));
+    assert_eq!(strict.err(), Some(Errors::InvalidClient("2".to_string())));
+    assert!(restored.account(4, DEFAULT_ASSET).is_none());
+    assert!(restored.transaction(4).is_none());
+    assert_eq!(restored.account_snapshots(), engine.account_snapshots());
+}
+
+/// Withdrawal limit windows are part of the saved state, so limits behave
+/// the same on a restored engine and in simulations, which leave the
+/// windows unchanged.
+///
+/// Limits: 1 withdrawal per hour.
+///
+/// State:
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 1, 10.0, 0`
+///
+/// `withdrawal, 1, 2, 1.0, 10`
+///
+/// Batch:
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `withdrawal, 1, 3, 1.0, 20` (window count)
+///
+/// `withdrawal, 1, 4, 1.0, 3700`
+///
+/// Expected changes:
+///
+/// `client, asset, available, held, total, locked, available_change, held_change, total_change`
+///
+/// `1, default, 8, 0, 8, false, -1, 0, -1`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn simulate_withdrawal_windows() {
+    let config = r#"{ "limits": { "default": { "window_seconds": 3600, "window_count": 1 } } }"#;
+    let mut engine = Engine::with_config(config::Config::from_json(config).unwrap());
+    process_csv(&mut engine, "type, client, tx, amount, timestamp\ndeposit, 1, 1, 10.0, 0\nwithdrawal, 1, 2, 1.0, 10".as_bytes(), Mode::Strict).unwrap();
+    let state: EngineState = serde_json::from_str(&serde_json::to_string(&engine.state()).unwrap()).unwrap();
+    assert_eq!(state.withdrawals, vec![WithdrawalSnapshot { client: 1, asset: DEFAULT_ASSET.to_string(), day: 0, day_amount: 1.0, window: vec![(10, 1.0)] }]);
+    let mut restored = Engine::with_config(config::Config::from_json(config).unwrap());
+    restored.restore(state);
+
+    let batch = "type, client, tx, amount, timestamp\nwithdrawal, 1, 3, 1.0, 20\nwithdrawal, 1, 4, 1.0, 3700";
+    for _ in 0..2 {
+        let (simulation, _) = restored.simulate(|engine| process_csv(engine, batch.as_bytes(), Mode::Strict)).unwrap();
+        assert_eq!(simulation.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>(), vec![(3, audit::RejectReason::WindowCountExceeded)]);
+        assert_eq!(fmt_simulation(&simulation), "client, asset, available, held, total, locked, available_change, held_change, total_change\n\
+            1, default, 8, 0, 8, false, -1, 0, -1");
+        assert_eq!(restored.state().withdrawals, engine.state().withdrawals);
+    }
+
+    //States saved before the windows were added restore empty windows
+    let state: EngineState = serde_json::from_str(r#"{"clock":10,"sequence":2,"accounts":[],"transactions":[],"stats":{}}"#).unwrap();
+    assert!(state.withdrawals.is_empty());
+}
+
+/// Balances are queried as of a line, a tx or a timestamp by replaying the
+/// input, going back to checkpoints for earlier points. The input is read
+/// up to the point of each query, from memory or streamed from files.
+///
+/// Input:
+///
+/// `type, client, tx, amount, timestamp`
+///
+/// `deposit, 1, 1, 5.0, 100`
+///
+/// `withdrawal, 1, 2, 1.0, 200`
+///
+/// `deposit, 2, 3, 2.0,`
+///
+/// `dispute, 1, 1, , 300`
+///
+/// `chargeback, 1, 1, , 400`
+///
+/// Expected (client 1 available, held, locked):
+///
+/// line 2: `4.0, 0.0, false`, timestamp 300: `-1.0, 5.0, false`,
+/// tx 1: `5.0, 0.0, false`, line 5: `-1.0, 0.0, true`
+#[cfg(feature = "csv")]
+#[test]
+fn point_in_time_balances() {
+    use replay::{AsOf, Replay};
+
+    let input = "
+    type, client, tx, amount, timestamp
+    deposit, 1, 1, 5.0, 100
+    withdrawal, 1, 2, 1.0, 200
+    deposit, 2, 3, 2.0,
+    dispute, 1, 1, , 300
+    chargeback, 1, 1, , 400
+    deposit, invalid_client, 4, 1.0, 500";
+    let rows: Vec<(usize, replay::LogRow)> = csv_reader(input.as_bytes()).deserialize::<Input>().enumerate()
+        .map(|(index, row)| (0, (index as i32 + 1, row.map_err(|_| Errors::InvalidRecord((index + 1).to_string())))))
+        .collect();
+    let mut replay = Replay::new(Engine::new(), rows, 2, Mode::Strict);
+    let mut client = |as_of| {
+        let engine = replay.as_of(as_of).unwrap();
+        let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+        (acc.available(), acc.held(), acc.locked(), engine.accounts().len())
+    };
+
+    assert_eq!(client(AsOf::Line(2)), (4.0, 0.0, false, 1));
+    assert_eq!(client(AsOf::Timestamp(300)), (-1.0, 5.0, false, 2));
+    assert_eq!(client(AsOf::Tx(1)), (5.0, 0.0, false, 1));
+    assert_eq!(client(AsOf::Line(5)), (-1.0, 0.0, true, 2));
+    assert_eq!(client(AsOf::Timestamp(150)), (5.0, 0.0, false, 1));
+
+    assert_eq!(replay.position(AsOf::Tx(9)), None);
+    assert_eq!(replay.as_of(AsOf::Line(6)).err(), Some(Errors::InvalidClient("6".to_string())));
+    assert_eq!(replay.as_of(AsOf::Line(10)).err(), Some(Errors::InvalidClient("6".to_string())));
+    // The invalid row is reported once, with the index of its input
+    assert_eq!(replay.drain_errors(), vec![(0, Errors::InvalidClient("6".to_string()))]);
+
+    // Files are streamed and only read up to the point of the query
+    let dir = std::env::temp_dir().join(format!("point_in_time_balances_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = vec![dir.join("day1.csv"), dir.join("day2.csv")];
+    std::fs::write(&files[0], input.trim().split('\n').map(str::trim).collect::<Vec<&str>>().join("\n")).unwrap();
+    std::fs::write(&files[1], "type, client, tx, amount\ndispute, 2, 1, \ndeposit, 2, 7, 1.0").unwrap();
+    let read = std::rc::Rc::new(std::cell::Cell::new(0));
+    let counter = read.clone();
+    let log = io::stream_rows(&files, false).unwrap().inspect(move |_| counter.set(counter.get() + 1));
+    let mut replay = Replay::new(Engine::new(), log, 2, Mode::Strict);
+    let mut client = |as_of| {
+        let engine = replay.as_of(as_of).unwrap();
+        let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+        (acc.available(), acc.held(), acc.locked(), engine.accounts().len())
+    };
+    assert_eq!(client(AsOf::Line(2)), (4.0, 0.0, false, 1));
+    assert_eq!(read.get(), 2);
+    assert_eq!(client(AsOf::Timestamp(300)), (-1.0, 5.0, false, 2));
+    assert_eq!(client(AsOf::Tx(1)), (5.0, 0.0, false, 1));
+    assert_eq!(read.get(), 5);
+    assert_eq!(replay.as_of(AsOf::Line(6)).err(), Some(Errors::InvalidClient("6".to_string())));
+    assert_eq!(replay.drain_errors(), vec![(0, Errors::InvalidClient("6".to_string()))]);
+
+    // Skipped errors are returned with their file, including security errors
+    let mut replay = Replay::new(Engine::new(), io::stream_rows(&files, false).unwrap(), 2, Mode::Lenient);
+    assert!(replay.as_of(AsOf::Tx(7)).is_ok());
+    assert_eq!(replay.drain_errors(), vec![(0, Errors::InvalidClient("6".to_string())), (1, Errors::SecurityErrDivergentClientId("1".to_string()))]);
+    assert_eq!(replay.as_of(AsOf::Tx(9)).err(), Some(Errors::InvalidInput("no row for tx 9".to_string())));
+    assert!(replay.drain_errors().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Each applied row raises a `BalanceChanged` event per changed account,
+/// including the fee house account, while skipped rows raise none.
+///
+/// Input (withdrawal fee of 0.5 credited to client 0):
+///
+/// `type, client, tx, amount, to`
+///
+/// `deposit, 1, 1, 5.0,`
+///
+/// `transfer, 1, 2, 2.0, 2`
+///
+/// `withdrawal, 2, 3, 9.0,` (insufficient funds)
+///
+/// `withdrawal, 1, 4, 1.0,`
+///
+/// `dispute, 1, 2, ,` (held on the recipient account)
+///
+/// Expected (line, client, available, held, total):
+///
+/// `1, 1, 5.0, 0.0, 5.0`, `2, 1, 3.0, 0.0, 3.0`, `2, 2, 2.0, 0.0, 2.0`,
+/// `4, 0, 0.5, 0.0, 0.5`, `4, 1, 1.5, 0.0, 1.5`, `5, 2, 0.0, 2.0, 2.0`
+#[cfg(all(feature = "csv", feature = "json"))]
+#[test]
+fn balance_history() {
+    let config = config::Config::from_json(r#"{ "fees": { "house_account": 0, "withdrawal": { "flat": 0.5 } } }"#).unwrap();
+    let (subscriber, receiver) = events::channel();
+    let mut engine = Engine::with_config(config);
+    engine.subscribe(Box::new(subscriber));
+
+    let input = "
+    type, client, tx, amount, to
+    deposit, 1, 1, 5.0,
+    transfer, 1, 2, 2.0, 2
+    withdrawal, 2, 3, 9.0,
+    withdrawal, 1, 4, 1.0,
+    dispute, 1, 2, ,";
+    process_csv(&mut engine, input.as_bytes(), Mode::Strict).unwrap();
+    drop(engine);
+
+    let history: Vec<(i32, u16, f32, f32, f32)> = receiver.iter().filter_map(|event| match event {
+        events::Event::BalanceChanged { line, client, available, held, total, .. } => Some((line, client, available, held, total)),
+        _ => None,
+    }).collect();
+    assert_eq!(history, vec![
+        (1, 1, 5.0, 0.0, 5.0),
+        (2, 1, 3.0, 0.0, 3.0),
+        (2, 2, 2.0, 0.0, 2.0),
+        (4, 0, 0.5, 0.0, 0.5),
+        (4, 1, 1.5, 0.0, 1.5),
+        (5, 2, 0.0, 2.0, 2.0),
+    ]);
+}
+
+/// The run summary counts rows by operation and outcome and the amounts they moved.
+///
+/// Input (lenient mode, deposits above 100 held for review):
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0`
+///
+/// `deposit, 2, 2, 5.0`
+///
+/// `withdrawal, 1, 3, 4.0`
+///
+/// `withdrawal, 2, 4, 9.0` (insufficient funds)
+///
+/// `withdrawal, 1, 5,` (invalid amount)
+///
+/// `dispute, 2, 2,`
+///
+/// `chargeback, 2, 2,`
+///
+/// `deposit, 2, 6, 1.0` (locked account)
+///
+/// `deposit, 1, 7, 1.0`
+///
+/// `dispute, 1, 7,`
+///
+/// `deposit, 1, 1, 10.0` (duplicate)
+///
+/// `dispute, 1, 99,` (unknown tx, ignored)
+///
+/// `resolve, 1, 3,` (undisputed tx, ignored)
+///
+/// `deposit, 1, 8, 500.0` (held, then applied once reviewed)
+///
+/// Expected: 7 accepted rows, 2 ignored, 1 held, 2 failed, 1 invalid amount
+/// and 1 duplicate, 16.0 deposited, 4.0 withdrawn, 1.0 held, 5.0 charged back
+/// and 1 locked account. Once the held deposit is applied: 8 accepted rows
+/// and 516.0 deposited.
+#[cfg(feature = "csv")]
+#[test]
+fn run_summary() {
+    struct LargeDeposits;
+    impl rules::Rule for LargeDeposits {
+        fn name(&self) -> &str {
+            "large_deposits"
+        }
+
+        fn evaluate(&self, row: &Input, _engine: &Engine) -> rules::Decision {
+            match (row.op_type(), row.amount()) {
+                (Some(Operation::Deposit), Some(amount)) if amount > 100.0 => rules::Decision::Hold("large deposit".to_string()),
+                _ => rules::Decision::Allow,
+            }
+        }
+    }
+
+    let input = "
+    type, client, tx, amount
+    deposit, 1, 1, 10.0
+    deposit, 2, 2, 5.0
+    withdrawal, 1, 3, 4.0
+    withdrawal, 2, 4, 9.0
+    withdrawal, 1, 5,
+    dispute, 2, 2,
+    chargeback, 2, 2,
+    deposit, 2, 6, 1.0
+    deposit, 1, 7, 1.0
+    dispute, 1, 7,
+    deposit, 1, 1, 10.0
+    dispute, 1, 99,
+    resolve, 1, 3,
+    deposit, 1, 8, 500.0";
+    let mut engine = Engine::new();
+    engine.add_rule(Box::new(LargeDeposits));
+    let errors = process_csv(&mut engine, input.as_bytes(), Mode::Lenient).unwrap();
+    assert_eq!(errors, vec![Errors::InvalidAmount("5".to_string())]);
+
+    let summary = engine.summary();
+    assert_eq!(summary, summary::Summary {
+        rows: 14,
+        operations: [("chargeback", 1), ("deposit", 6), ("dispute", 3), ("resolve", 1), ("withdrawal", 3)].into_iter().collect(),
+        accepted: 7,
+        ignored: 2,
+        held_for_review: 1,
+        rejected: [("duplicate_transaction", 1), ("failed", 2), ("invalid_amount", 1)].into_iter().collect(),
+        deposited: 16.0,
+        withdrawn: 4.0,
+        held: 1.0,
+        charged_back: 5.0,
+        locked_accounts: 1,
+        elapsed_seconds: summary.elapsed_seconds,
+        rows_per_second: summary.rows_per_second,
+    });
+    assert!(summary.rows_per_second > 0.0);
+
+    for held in engine.take_held() {
+        engine.apply_reviewed(held).unwrap();
+    }
+    let summary = engine.summary();
+    assert_eq!((summary.rows, summary.accepted, summary.held_for_review, summary.deposited), (14, 8, 0, 516.0));
+}
@@ -1,33 +1,454 @@
-use std::env;
-use rust_coding_test::csv_read;
-use rust_coding_test::fmt_output;
-fn main() {
-    let arguments: Vec<String> = env::args().collect();
-    let mut is_csv = true;
-    let input: &str= match arguments.len() {
-        1 => {
-            //This is used in case arguments are not provided
-            //Allows manipulating input directly through code, 
-            //without needing a local CSV file.
-            is_csv = false;
-            let input_string = "
-            type, client, tx, amount
-            deposit, 1, 1, 1.0
-            deposit, 2, 2, 2.0
-            deposit, 1, 3, 2.0
-            withdrawal, 1, 4, 1.5
-            withdrawal, 2, 5, 3.0";
-            &input_string
-        }
-        _ => {
-            let input_csv = &arguments[1];
-            &input_csv
-        }
-    };
-
-
-    match csv_read(&input, is_csv){
-        Ok(accs) => println!("{}", fmt_output(accs)),
-        Err(_e) => println!("{}", _e),
+use std::{collections::HashSet, env, fs::{self, File}, io::{self, BufWriter, Write}, mem, path::{Path, PathBuf}, process, sync::{Arc, Mutex}};
+use rust_coding_test::{audit::{AuditEntry, Rejection}, config::Config, fmt_asset_output, fmt_output_json, fmt_simulation, Engine, EngineState, Errors, HeldTransaction};
+use rust_coding_test::io::{expand_inputs, merge_files, open_input, process_files, stream_rows, InputError};
+use rust_coding_test::replay::Replay;
+use rust_coding_test::reconcile::{fmt_differences, read_expected, reconcile};
+use rust_coding_test::events::{Event, Subscriber};
+mod cli;
+use crate::cli::{BalancesArgs, Command, Format, HistoryFormat, ProcessArgs, ReconcileArgs, SimulateArgs};
+
+const EXIT_INPUT_ERROR: i32 = 1;
+const EXIT_USAGE_ERROR: i32 = 2;
+const EXIT_SECURITY_ERROR: i32 = 3;
+const EXIT_DIFFERENCES: i32 = 4;
+
+/// Rows replayed between two checkpoints by the `balances` command.
+const CHECKPOINT_INTERVAL: usize = 10_000;
+
+fn exit_code(error: &Errors) -> i32 {
+    if error.is_security() {
+        EXIT_SECURITY_ERROR
+    } else {
+        EXIT_INPUT_ERROR
+    }
+}
+
+/// Write errors to the errors file when provided, stderr otherwise.
+fn report_errors(errors: &[InputError], errors_file: &Option<String>) -> Result<(), String> {
+    match errors_file {
+        Some(path) => {
+            let mut report = String::new();
+            for error in errors {
+                report.push_str(&format!("{}\n", error));
+            }
+            fs::write(path, report).map_err(|e| format!("Unable to write errors file {}: {}", path, e))
+        }
+        None => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Write one entry per line, e.g. JSON lines reports.
+fn write_lines(path: &str, lines: &[String]) -> std::io::Result<()> {
+    let mut content = String::new();
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    fs::write(path, content)
+}
+
+/// Write the output to the output file when provided, stdout otherwise.
+fn write_output(output: &str, path: &Option<String>) -> Result<(), i32> {
+    match path {
+        Some(path) => fs::write(path, format!("{}\n", output)).map_err(|e| {
+            eprintln!("Unable to write output {}: {}", path, e);
+            EXIT_INPUT_ERROR
+        }),
+        None => {
+            println!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+/// Balance history file, written by a `HistoryWriter` subscriber.
+struct HistoryFile {
+    out: BufWriter<File>,
+    format: HistoryFormat,
+    /// Clients of the history, all clients when `None`.
+    clients: Option<HashSet<u16>>,
+    /// First write error, nothing is written once set.
+    error: Option<io::Error>,
+}
+
+impl HistoryFile {
+    fn create(path: &str, format: HistoryFormat, clients: Option<HashSet<u16>>) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        if format == HistoryFormat::Csv {
+            writeln!(out, "line, tx, operation, client, asset, available, held, total, locked")?;
+        }
+        Ok(HistoryFile { out, format, clients, error: None })
+    }
+
+    fn write(&mut self, event: &Event) -> io::Result<()> {
+        let Event::BalanceChanged { line, tx, operation, client, asset, available, held, total, locked } = event else {
+            return Ok(());
+        };
+        if self.clients.as_ref().is_some_and(|clients| !clients.contains(client)) {
+            return Ok(());
+        }
+        match self.format {
+            HistoryFormat::Csv => {
+                let operation = format!("{:?}", operation).to_lowercase();
+                writeln!(self.out, "{}, {}, {}, {}, {}, {}, {}, {}, {}", line, tx, operation, client, asset, available, held, total, locked)
+            }
+            HistoryFormat::Ndjson => writeln!(self.out, "{}", serde_json::to_string(event).unwrap()),
+        }
+    }
+
+    /// Flush the history, returning the first write error.
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// Balance history file and its path.
+type History = (String, Arc<Mutex<HistoryFile>>);
+
+/// Subscriber writing the balance history, shared with `run` to report errors.
+struct HistoryWriter(Arc<Mutex<HistoryFile>>);
+
+impl Subscriber for HistoryWriter {
+    fn on_event(&mut self, event: &Event) {
+        let mut file = self.0.lock().unwrap();
+        if file.error.is_none() {
+            if let Err(e) = file.write(event) {
+                file.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Expand the input files and build the engine from the configuration.
+fn setup(args: &ProcessArgs) -> Result<(Engine, Vec<PathBuf>), i32> {
+    let files = match expand_inputs(&args.files) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(exit_code(&e));
+        }
+    };
+    let config = match &args.config {
+        Some(path) => match Config::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(exit_code(&e));
+            }
+        },
+        None => Config::default(),
+    };
+    Ok((Engine::with_config(config), files))
+}
+
+fn apply_files(engine: &mut Engine, files: &[PathBuf], args: &ProcessArgs) -> Result<Vec<InputError>, InputError> {
+    match args.merge {
+        true => merge_files(engine, files, args.mode),
+        false => process_files(engine, files, args.mode),
+    }
+}
+
+/// Report the processing errors.
+///
+/// Returns the exit code of the skipped errors, or the exit code of the failure.
+fn check_errors(processed: Result<Vec<InputError>, InputError>, args: &ProcessArgs) -> Result<i32, i32> {
+    let (errors, aborted) = match processed {
+        Ok(skipped) => (skipped, false),
+        Err(error) => (vec![error], true),
+    };
+
+    if let Err(e) = report_errors(&errors, &args.errors_file) {
+        eprintln!("{}", e);
+        return Err(EXIT_INPUT_ERROR);
+    }
+    if aborted {
+        if args.errors_file.is_some() {
+            eprintln!("{}", errors[0]);
+        }
+        return Err(exit_code(&errors[0].error));
+    }
+
+    // Skipped input errors are expected in lenient mode,
+    // security errors are always reported through the exit code.
+    match errors.iter().find(|e| e.error.is_security()) {
+        Some(e) => Ok(exit_code(&e.error)),
+        None => Ok(0),
+    }
+}
+
+/// Write the audit, rejections and held transactions files when requested.
+///
+/// Held transactions are not applied, so they are reported on stderr
+/// when no held transactions file is requested.
+fn write_reports(audit: &[AuditEntry], rejections: &[Rejection], held: &[HeldTransaction], args: &ProcessArgs) -> Result<(), i32> {
+    if let Some(path) = &args.audit_file {
+        let entries: Vec<String> = audit.iter().map(|entry| entry.to_json()).collect();
+        if let Err(e) = write_lines(path, &entries) {
+            eprintln!("Unable to write audit file {}: {}", path, e);
+            return Err(EXIT_INPUT_ERROR);
+        }
+    }
+    if let Some(path) = &args.rejections_file {
+        let rejections: Vec<String> = rejections.iter().map(|rejection| rejection.to_json()).collect();
+        if let Err(e) = write_lines(path, &rejections) {
+            eprintln!("Unable to write rejections file {}: {}", path, e);
+            return Err(EXIT_INPUT_ERROR);
+        }
+    }
+    match &args.held_file {
+        Some(path) => {
+            let held: Vec<String> = held.iter().map(|held| serde_json::to_string(held).unwrap()).collect();
+            if let Err(e) = write_lines(path, &held) {
+                eprintln!("Unable to write held transactions file {}: {}", path, e);
+                return Err(EXIT_INPUT_ERROR);
+            }
+        }
+        None if !held.is_empty() => eprintln!("{} transactions held for review were not applied, use --held-file to write them", held.len()),
+        None => {}
+    }
+    Ok(())
+}
+
+/// Print the summary of the run and write it when requested,
+/// including the rows applied before a failure.
+fn write_summary(engine: &Engine, args: &ProcessArgs) -> Result<(), i32> {
+    if !args.summary && args.summary_file.is_none() {
+        return Ok(());
     }
+    let summary = engine.summary();
+    if args.summary {
+        eprintln!("{}", summary);
+    }
+    if let Some(path) = &args.summary_file {
+        if let Err(e) = fs::write(path, serde_json::to_string_pretty(&summary).unwrap()) {
+            eprintln!("Unable to write summary file {}: {}", path, e);
+            return Err(EXIT_INPUT_ERROR);
+        }
+    }
+    Ok(())
+}
+
+/// Create the balance history file when requested, see `subscribe_history`.
+fn create_history(args: &ProcessArgs) -> Result<Option<History>, i32> {
+    let Some(path) = &args.history else {
+        return Ok(None);
+    };
+    match HistoryFile::create(path, args.history_format, args.history_clients.as_ref().map(|clients| clients.iter().copied().collect())) {
+        Ok(file) => Ok(Some((path.clone(), Arc::new(Mutex::new(file))))),
+        Err(e) => {
+            eprintln!("Unable to write history file {}: {}", path, e);
+            Err(EXIT_INPUT_ERROR)
+        }
+    }
+}
+
+/// Write the balance history of the rows applied to `engine` from now on.
+fn subscribe_history(engine: &mut Engine, history: &Option<History>) {
+    if let Some((_, file)) = history {
+        engine.subscribe(Box::new(HistoryWriter(file.clone())));
+    }
+}
+
+fn finish_history(history: Option<History>) -> Result<(), i32> {
+    if let Some((path, file)) = history {
+        if let Err(e) = file.lock().unwrap().finish() {
+            eprintln!("Unable to write history file {}: {}", path, e);
+            return Err(EXIT_INPUT_ERROR);
+        }
+    }
+    Ok(())
+}
+
+/// Process the input files with `apply` and write the reports and the engine state.
+///
+/// Returns the engine and the exit code of the skipped errors,
+/// or the exit code of the failure.
+fn run<F>(args: &ProcessArgs, apply: F) -> Result<(Engine, i32), i32>
+where
+    F: FnOnce(&mut Engine, &[PathBuf]) -> Result<Vec<InputError>, InputError>,
+{
+    let (mut engine, files) = setup(args)?;
+    let history = create_history(args)?;
+    subscribe_history(&mut engine, &history);
+    let processed = apply(&mut engine, &files);
+    write_summary(&engine, args)?;
+    let code = check_errors(processed, args)?;
+    write_reports(&engine.drain_audit(), &engine.drain_rejections(), &engine.take_held(), args)?;
+    finish_history(history)?;
+
+    if let Some(path) = &args.save_state {
+        if let Err(e) = fs::write(path, serde_json::to_string(&engine.state()).unwrap()) {
+            eprintln!("Unable to write state file {}: {}", path, e);
+            return Err(EXIT_INPUT_ERROR);
+        }
+    }
+    Ok((engine, code))
+}
+
+fn process(args: ProcessArgs) -> i32 {
+    let (engine, code) = match run(&args, |engine, files| apply_files(engine, files, &args)) {
+        Ok(processed) => processed,
+        Err(code) => return code,
+    };
+    let output = match args.format {
+        Format::Csv => fmt_asset_output(engine.into_accounts(), args.status),
+        Format::Json => fmt_output_json(engine.into_accounts(), args.status),
+    };
+    match write_output(&output, &args.output) {
+        Ok(()) => code,
+        Err(code) => code,
+    }
+}
+
+fn reconcile_accounts(args: ReconcileArgs) -> i32 {
+    let (engine, code) = match run(&args.process, |engine, files| apply_files(engine, files, &args.process)) {
+        Ok(processed) => processed,
+        Err(code) => return code,
+    };
+    let expected = open_input(Path::new(&args.expected))
+        .map_err(|e| Errors::InvalidInput(e.to_string()))
+        .and_then(read_expected);
+    let expected = match expected {
+        Ok(expected) => expected,
+        Err(e) => {
+            eprintln!("{}: {}", args.expected, e);
+            return EXIT_INPUT_ERROR;
+        }
+    };
+    let differences = reconcile(engine.accounts(), &expected, args.tolerance);
+    let output = match args.process.format {
+        Format::Csv => fmt_differences(&differences),
+        Format::Json => serde_json::to_string_pretty(&differences).unwrap(),
+    };
+    if let Err(code) = write_output(&output, &args.process.output) {
+        return code;
+    }
+    match code {
+        0 if !differences.is_empty() => EXIT_DIFFERENCES,
+        code => code,
+    }
+}
+
+fn simulate(args: SimulateArgs) -> i32 {
+    let (mut engine, files) = match setup(&args.process) {
+        Ok(setup) => setup,
+        Err(code) => return code,
+    };
+    let state = fs::read_to_string(&args.state)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str::<EngineState>(&content).map_err(|e| e.to_string()));
+    match state {
+        Ok(state) => engine.restore(state),
+        Err(e) => {
+            eprintln!("Unable to read engine state {}: {}", args.state, e);
+            return EXIT_INPUT_ERROR;
+        }
+    }
+
+    let history = match create_history(&args.process) {
+        Ok(history) => history,
+        Err(code) => return code,
+    };
+    // History and summary cover the simulated batch only
+    let simulated = engine.simulate(|engine| {
+        subscribe_history(engine, &history);
+        let processed = apply_files(engine, &files, &args.process);
+        let summary = write_summary(engine, &args.process);
+        processed.map(|skipped| (skipped, summary))
+    });
+    let (simulation, (skipped, summary)) = match simulated {
+        Ok(simulated) => simulated,
+        Err(error) => return match check_errors(Err(error), &args.process) {
+            Ok(code) | Err(code) => code,
+        },
+    };
+    if let Err(code) = summary {
+        return code;
+    }
+    let code = match check_errors(Ok(skipped), &args.process) {
+        Ok(code) => code,
+        Err(code) => return code,
+    };
+    if let Err(code) = finish_history(history) {
+        return code;
+    }
+    if let Err(code) = write_reports(&simulation.audit, &simulation.rejections, &simulation.held, &args.process) {
+        return code;
+    }
+
+    let output = match args.process.format {
+        Format::Csv => {
+            if args.process.rejections_file.is_none() {
+                for rejection in simulation.rejections.iter() {
+                    eprintln!("{}", rejection);
+                }
+            }
+            fmt_simulation(&simulation)
+        }
+        Format::Json => serde_json::to_string_pretty(&simulation).unwrap(),
+    };
+    match write_output(&output, &args.process.output) {
+        Ok(()) => code,
+        Err(code) => code,
+    }
+}
+
+/// Replay `files` up to the point of `args`, leaving `engine` at that point.
+fn replay_files(engine: &mut Engine, files: &[PathBuf], args: &BalancesArgs) -> Result<Vec<InputError>, InputError> {
+    let log = stream_rows(files, args.process.merge)?;
+    let mut replay = Replay::new(mem::take(engine), log, CHECKPOINT_INTERVAL, args.process.mode);
+    let replayed = replay.as_of(args.as_of).map(|_| ());
+    let mut errors: Vec<InputError> = replay.drain_errors().into_iter()
+        .map(|(index, error)| InputError { origin: files[index].display().to_string(), error })
+        .collect();
+    *engine = replay.into_engine();
+    match replayed {
+        Ok(()) => Ok(errors),
+        // Invalid rows are reported last, a missing point is reported for every file
+        Err(error) => Err(match errors.pop() {
+            Some(failed) if failed.error == error => failed,
+            _ => InputError { origin: files.iter().map(|file| file.display().to_string()).collect::<Vec<String>>().join(", "), error },
+        }),
+    }
+}
+
+fn balances(args: BalancesArgs) -> i32 {
+    let (engine, code) = match run(&args.process, |engine, files| replay_files(engine, files, &args)) {
+        Ok(processed) => processed,
+        Err(code) => return code,
+    };
+    let output = match args.process.format {
+        Format::Csv => fmt_asset_output(engine.into_accounts(), args.process.status),
+        Format::Json => fmt_output_json(engine.into_accounts(), args.process.status),
+    };
+    match write_output(&output, &args.process.output) {
+        Ok(()) => code,
+        Err(code) => code,
+    }
+}
+
+fn main() {
+    let code = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            0
+        }
+        Ok(Command::Process(args)) => process(args),
+        Ok(Command::Reconcile(args)) => reconcile_accounts(args),
+        Ok(Command::Simulate(args)) => simulate(args),
+        Ok(Command::Balances(args)) => balances(args),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            EXIT_USAGE_ERROR
+        }
+    };
+    process::exit(code);
 }
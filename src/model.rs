@@ -0,0 +1,403 @@
+//! Data model of the engine: input rows, recorded transactions and accounts.
+//!
+//! Accounts and transactions are read through accessors, or copied into
+//! the serializable `AccountSnapshot` and `TransactionSnapshot` structs.
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+
+/// Deserialize invalid values as `None`, letting the engine report them.
+fn invalid_option<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    Option<T>: serde::Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).or(Ok(None))
+}
+
+/// Struct for processing CSV fields.
+/// 
+/// Invalid Fields are filtered and sanitized by the application.
+/// The `timestamp` column is optional and only used when merging streams.
+/// The `asset` column is optional, rows without asset apply to `DEFAULT_ASSET`.
+/// The `to` column is only used by transfers, holding the receiving client.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Input{
+    #[serde(rename = "type",deserialize_with = "invalid_option")]
+    pub(crate) op_type: Option<Operation>,
+    #[serde(deserialize_with = "invalid_option")]
+    pub(crate) client: Option<u16>,
+    #[serde(deserialize_with = "invalid_option")]
+    pub(crate) tx: Option<u32>,
+    #[serde(default, deserialize_with = "invalid_option")]
+    pub(crate) amount: Option<f32>,
+    #[serde(default, deserialize_with = "invalid_option")]
+    pub(crate) timestamp: Option<u64>,
+    #[serde(default, deserialize_with = "invalid_option")]
+    pub(crate) asset: Option<String>,
+    #[serde(default, deserialize_with = "invalid_option")]
+    pub(crate) to: Option<u16>
+}
+
+/// Asset used when the `asset` column is absent or empty.
+pub const DEFAULT_ASSET: &str = "default";
+
+/// Accounts are kept per client and asset.
+pub type AccountKey = (u16, String);
+
+impl Input {
+    /// Row of `operation` for embedding code building rows without a serde format.
+    pub fn new(operation: Operation, client: u16, tx: u32, amount: Option<f32>) -> Self {
+        Input { op_type: Some(operation), client: Some(client), tx: Some(tx), amount, timestamp: None, asset: None, to: None }
+    }
+
+    pub fn deposit(client: u16, tx: u32, amount: f32) -> Self {
+        Input::new(Operation::Deposit, client, tx, Some(amount))
+    }
+
+    pub fn withdrawal(client: u16, tx: u32, amount: f32) -> Self {
+        Input::new(Operation::Withdrawal, client, tx, Some(amount))
+    }
+
+    /// Dispute of the whole disputable amount of `tx`, see `with_amount` for partial disputes.
+    pub fn dispute(client: u16, tx: u32) -> Self {
+        Input::new(Operation::Dispute, client, tx, None)
+    }
+
+    pub fn resolve(client: u16, tx: u32) -> Self {
+        Input::new(Operation::Resolve, client, tx, None)
+    }
+
+    pub fn chargeback(client: u16, tx: u32) -> Self {
+        Input::new(Operation::Chargeback, client, tx, None)
+    }
+
+    /// Transfer of `amount` from `client` to `to`.
+    pub fn transfer(client: u16, tx: u32, amount: f32, to: u16) -> Self {
+        Input { to: Some(to), ..Input::new(Operation::Transfer, client, tx, Some(amount)) }
+    }
+
+    /// Same row with `amount`, e.g. the amount of a partial dispute.
+    pub fn with_amount(self, amount: f32) -> Self {
+        Input { amount: Some(amount), ..self }
+    }
+
+    pub fn with_asset(self, asset: &str) -> Self {
+        Input { asset: Some(asset.to_string()), ..self }
+    }
+
+    pub fn with_timestamp(self, timestamp: u64) -> Self {
+        Input { timestamp: Some(timestamp), ..self }
+    }
+
+    pub fn op_type(&self) -> Option<Operation> {
+        self.op_type
+    }
+
+    pub fn client(&self) -> Option<u16> {
+        self.client
+    }
+
+    pub fn tx(&self) -> Option<u32> {
+        self.tx
+    }
+
+    pub fn amount(&self) -> Option<f32> {
+        self.amount
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Receiving client of a transfer.
+    pub fn to(&self) -> Option<u16> {
+        self.to
+    }
+
+    pub fn asset(&self) -> &str {
+        match &self.asset {
+            Some(asset) if !asset.is_empty() => asset,
+            _ => DEFAULT_ASSET,
+        }
+    }
+
+    /// Whether the row is an exact resend of `other`: same type, client, tx,
+    /// amount, asset, recipient and timestamp.
+    pub(crate) fn is_duplicate_of(&self, other: &Input) -> bool {
+        self.op_type == other.op_type
+            && self.client == other.client
+            && self.tx == other.tx
+            && self.amount == other.amount
+            && self.asset() == other.asset()
+            && self.to == other.to
+            && self.timestamp == other.timestamp
+    }
+
+    /// Account affected by the row. The client must have been validated.
+    pub(crate) fn account_key(&self) -> AccountKey {
+        (self.client.unwrap(), self.asset().to_string())
+    }
+
+    /// Receiving account of a transfer. The recipient must have been validated.
+    pub(crate) fn recipient_key(&self) -> AccountKey {
+        (self.to.unwrap(), self.asset().to_string())
+    }
+
+    /// Account holding the funds of the transaction, affected by disputes:
+    /// the recipient for transfers, the client otherwise.
+    pub(crate) fn disputed_account_key(&self) -> AccountKey {
+        match self.op_type {
+            Some(Operation::Transfer) => self.recipient_key(),
+            _ => self.account_key(),
+        }
+    }
+}
+
+/// Struct used for keeping dispute information of Transactions
+/// 
+/// This struct is used to keep track of disputes in chargeback 
+/// and resolve operations. Disputes can be partial: `disputed` is
+/// the amount currently held, `disputable` the amount that can still
+/// be disputed.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Txs{
+    #[serde(rename = "row")]
+    pub(crate) info: Input,
+    pub(crate) status: TxStatus,
+    pub(crate) disputed: f32,
+    pub(crate) disputable: f32,
+    /// Engine clock and row sequence number when the transaction was recorded,
+    /// used by dispute windows.
+    pub(crate) timestamp: u64,
+    pub(crate) sequence: u64
+}
+
+/// Outcome of a recorded transaction.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStatus {
+    Applied,
+    /// Not applied (missing account, insufficient funds, locked account,
+    /// withdrawal limits): kept for duplicate detection, cannot be disputed.
+    Failed,
+}
+
+impl Txs {
+    pub(crate) fn new(info: Input, timestamp: u64, sequence: u64) -> Self {
+        let disputable = info.amount.map_or(0.0, Funds::get_amount);
+        Txs { info, status: TxStatus::Applied, disputed: 0.0, disputable, timestamp, sequence }
+    }
+
+    /// Row the transaction was recorded from.
+    pub fn input(&self) -> &Input {
+        &self.info
+    }
+
+    pub fn status(&self) -> TxStatus {
+        self.status
+    }
+
+    /// Amount currently held by disputes.
+    pub fn disputed(&self) -> f32 {
+        self.disputed
+    }
+
+    /// Amount that can still be disputed.
+    pub fn disputable(&self) -> f32 {
+        self.disputable
+    }
+
+    pub fn in_dispute(&self) -> bool {
+        self.disputed > 0.0
+    }
+}
+
+/// Available operations for the Wallet.
+
+#[derive(Debug, Deserialize, Serialize,PartialEq,Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation{
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Transfer
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Funds(f64);
+
+/// Trait used to ensure 4 decimal places in the stored amount
+/// 
+/// Avoids importing large crates (e.g. rust_decimal) for a simple operation.
+/// 
+/// WARNING: the application does not currently support rounding up/down, only performing truncation.
+/// This is a known issue and depending on application requirements should be addressed.
+pub(crate) trait FundAccount{
+    fn get_amount(amount: f32) -> f32;
+}
+
+impl FundAccount for Funds {
+
+    fn get_amount(amount: f32) -> f32 {
+        (amount * 10000.0).round() / 10000.0
+    }
+}
+
+/// State of an account. Frozen and closed accounts are locked:
+/// they do not accept any other transaction.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Frozen by a chargeback, an auto lock trigger or an administrator.
+    /// Line and tx locate the transaction freezing the account, if any.
+    Frozen {
+        cause: FreezeCause,
+        reason: String,
+        line: Option<i32>,
+        tx: Option<u32>,
+    },
+    Closed,
+}
+
+/// Cause of an account freeze, `reason` giving the details.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FreezeCause {
+    Chargeback,
+    AutoLock,
+    Manual,
+}
+
+impl AccountStatus {
+    /// Frozen by the chargeback `tx` at `line`.
+    pub(crate) fn charged_back(line: i32, tx: u32) -> Self {
+        AccountStatus::Frozen { cause: FreezeCause::Chargeback, reason: "chargeback".to_string(), line: Some(line), tx: Some(tx) }
+    }
+
+    /// Frozen by an auto lock trigger reached by the transaction at `line`.
+    pub(crate) fn auto_locked(reason: &str, line: i32, tx: u32) -> Self {
+        AccountStatus::Frozen { cause: FreezeCause::AutoLock, reason: reason.to_string(), line: Some(line), tx: Some(tx) }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        *self != AccountStatus::Active
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen { .. } => "frozen",
+            AccountStatus::Closed => "closed",
+        }
+    }
+}
+
+/// Struct used for storing account information: 
+/// 
+/// Available, Held and Total amount. Also keeps  
+/// track of the account state (active/frozen/closed). 
+
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccInfo{
+    pub(crate) available: f32,
+    pub(crate) held: f32,
+    pub(crate) total: f32,
+    pub(crate) status: AccountStatus
+}
+
+impl AccInfo {
+    pub fn available(&self) -> f32 {
+        self.available
+    }
+
+    pub fn held(&self) -> f32 {
+        self.held
+    }
+
+    pub fn total(&self) -> f32 {
+        self.total
+    }
+
+    pub fn status(&self) -> &AccountStatus {
+        &self.status
+    }
+
+    /// Whether the account is locked, see `AccountStatus`.
+    pub fn locked(&self) -> bool {
+        self.status.is_locked()
+    }
+}
+
+/// Serializable copy of an account, see `Engine::account_snapshots`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub client: u16,
+    pub asset: String,
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+    pub locked: bool,
+    #[serde(flatten)]
+    pub status: AccountStatus,
+}
+
+impl AccountSnapshot {
+    pub fn new(key: &AccountKey, account: &AccInfo) -> Self {
+        AccountSnapshot {
+            client: key.0,
+            asset: key.1.clone(),
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked(),
+            status: account.status.clone(),
+        }
+    }
+
+    pub fn key(&self) -> AccountKey {
+        (self.client, self.asset.clone())
+    }
+
+    /// Account state of the snapshot, `locked` follows from the status.
+    pub fn account(&self) -> AccInfo {
+        AccInfo { available: self.available, held: self.held, total: self.total, status: self.status.clone() }
+    }
+}
+
+/// Serializable copy of a recorded transaction, see `Engine::transaction_snapshots`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TransactionSnapshot {
+    pub tx: u32,
+    pub client: u16,
+    pub operation: Operation,
+    pub asset: String,
+    pub amount: f32,
+    pub to: Option<u16>,
+    pub timestamp: u64,
+    pub status: TxStatus,
+    pub disputed: f32,
+    pub disputable: f32,
+}
+
+impl TransactionSnapshot {
+    /// Recorded transactions were validated: type, client, tx and amount are set.
+    pub fn new(txs: &Txs) -> Self {
+        TransactionSnapshot {
+            tx: txs.info.tx.unwrap(),
+            client: txs.info.client.unwrap(),
+            operation: txs.info.op_type.unwrap(),
+            asset: txs.info.asset().to_string(),
+            amount: txs.info.amount.unwrap_or_default(),
+            to: txs.info.to,
+            timestamp: txs.timestamp,
+            status: txs.status,
+            disputed: txs.disputed,
+            disputable: txs.disputable,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Reconciliation of the engine accounts against expected balances,
+//! e.g. exported from a ledger system in the `fmt_output` format.
+use std::{collections::HashMap, fmt, io::Read};
+use serde_derive::{Deserialize, Serialize};
+use crate::{csv_reader, AccInfo, AccountKey, Errors, DEFAULT_ASSET};
+
+/// Expected balances of an account.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExpectedAccount {
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+    pub locked: bool,
+}
+
+/// Row of an expected accounts file, the asset column is optional.
+#[derive(Deserialize)]
+struct ExpectedRow {
+    client: u16,
+    #[serde(default)]
+    asset: Option<String>,
+    available: f32,
+    held: f32,
+    total: f32,
+    locked: bool,
+}
+
+/// Read an accounts CSV, with the columns of `fmt_output` or `fmt_asset_output`.
+pub fn read_expected<R: Read>(reader: R) -> Result<HashMap<AccountKey,ExpectedAccount>, Errors> {
+    let mut expected = HashMap::new();
+    for (index, result) in csv_reader(reader).deserialize::<ExpectedRow>().enumerate() {
+        let line = index as i32 + 1;
+        let row = result.map_err(|_| Errors::InvalidRecord(line.to_string()))?;
+        let asset = row.asset.filter(|asset| !asset.is_empty()).unwrap_or_else(|| DEFAULT_ASSET.to_string());
+        let account = ExpectedAccount { available: row.available, held: row.held, total: row.total, locked: row.locked };
+        if expected.insert((row.client, asset), account).is_some() {
+            return Err(Errors::InvalidRecord(line.to_string()));
+        }
+    }
+    Ok(expected)
+}
+
+/// Accepted difference between expected and actual amounts.
+///
+/// An amount matches when it is within `absolute` of the expected amount,
+/// or within `relative` percent of it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub absolute: f32,
+    pub relative: f32,
+}
+
+impl Tolerance {
+    pub fn matches(&self, expected: f32, actual: f32) -> bool {
+        let difference = (expected - actual).abs();
+        difference <= self.absolute || difference <= expected.abs() * self.relative / 100.0
+    }
+}
+
+/// Difference between the expected and the actual accounts.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Difference {
+    /// Expected account not produced by the engine.
+    Missing { client: u16, asset: String },
+    /// Account produced by the engine but not expected.
+    Extra { client: u16, asset: String },
+    /// Field outside the tolerance.
+    Field { client: u16, asset: String, field: String, expected: String, actual: String },
+}
+
+impl Difference {
+    pub fn key(&self) -> (u16, &str) {
+        match self {
+            Difference::Missing { client, asset } | Difference::Extra { client, asset } | Difference::Field { client, asset, .. } => (*client, asset),
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Missing { client, asset } => write!(f, "Missing account for client {} and asset {}", client, asset),
+            Difference::Extra { client, asset } => write!(f, "Extra account for client {} and asset {}", client, asset),
+            Difference::Field { client, asset, field, expected, actual } =>
+                write!(f, "Different {} for client {} and asset {}: expected {}, actual {}", field, client, asset, expected, actual),
+        }
+    }
+}
+
+/// Compare the engine accounts against the expected ones.
+///
+/// Amounts are compared with the tolerance, the locked flag exactly.
+/// Differences are sorted by client and asset.
+pub fn reconcile(actual: &HashMap<AccountKey,AccInfo>, expected: &HashMap<AccountKey,ExpectedAccount>, tolerance: Tolerance) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for ((client, asset), wanted) in expected {
+        let (client, asset) = (*client, asset.clone());
+        let acc = match actual.get(&(client, asset.clone())) {
+            Some(acc) => acc,
+            None => {
+                differences.push(Difference::Missing { client, asset });
+                continue;
+            }
+        };
+        let amounts = [("available", wanted.available, acc.available), ("held", wanted.held, acc.held), ("total", wanted.total, acc.total)];
+        for (field, expected, actual) in amounts {
+            if !tolerance.matches(expected, actual) {
+                differences.push(Difference::Field { client, asset: asset.clone(), field: field.to_string(), expected: expected.to_string(), actual: actual.to_string() });
+            }
+        }
+        if wanted.locked != acc.locked() {
+            differences.push(Difference::Field { client, asset, field: "locked".to_string(), expected: wanted.locked.to_string(), actual: acc.locked().to_string() });
+        }
+    }
+    for (client, asset) in actual.keys().filter(|key| !expected.contains_key(*key)) {
+        differences.push(Difference::Extra { client: *client, asset: asset.clone() });
+    }
+    differences.sort_by(|a, b| a.key().cmp(&b.key()));
+    differences
+}
+
+/// Format the differences into a csv table.
+pub fn fmt_differences(differences: &[Difference]) -> String {
+    let mut output = "client, asset, kind, field, expected, actual".to_string();
+    for difference in differences {
+        output = match difference {
+            Difference::Missing { client, asset } => format!("{}\n{}, {}, missing, , , ", output, client, asset),
+            Difference::Extra { client, asset } => format!("{}\n{}, {}, extra, , , ", output, client, asset),
+            Difference::Field { client, asset, field, expected, actual } =>
+                format!("{}\n{}, {}, field, {}, {}, {}", output, client, asset, field, expected, actual),
+        };
+    }
+    output
+}
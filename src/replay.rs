@@ -0,0 +1,172 @@
+//! Point-in-time balances: an input log is replayed up to a line, a tx or
+//! a timestamp, resuming from the closest checkpoint taken on the way.
+use std::fmt;
+use crate::{engine::Checkpoint, Engine, Errors, Input, Mode};
+
+/// Row of an input log and the line of its input, used for locating errors.
+pub type LogRow = (i32, Result<Input, Errors>);
+
+/// Point of the log the balances are queried at, included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOf {
+    /// Rows of the log up to this line, numbered from 1.
+    Line(usize),
+    /// Rows of the log up to the first row with this tx.
+    Tx(u32),
+    /// Rows of the log up to this timestamp. Rows without timestamp
+    /// inherit the one of the previous row.
+    Timestamp(u64),
+}
+
+impl fmt::Display for AsOf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsOf::Line(line) => write!(f, "line {}", line),
+            AsOf::Tx(tx) => write!(f, "tx {}", tx),
+            AsOf::Timestamp(timestamp) => write!(f, "timestamp {}", timestamp),
+        }
+    }
+}
+
+/// Rows of an input log, streamed with the index of the input they come from.
+pub type LogRows = Box<dyn Iterator<Item = (usize, LogRow)>>;
+
+/// Replay of an input log against an engine, for one or several queries.
+///
+/// Rows are read from the log as the queries need them, so a query only
+/// reads the log up to its point. Every `interval` rows a checkpoint of the
+/// engine is taken, so later queries only replay the rows following the
+/// closest checkpoint. The rows read and every checkpoint (accounts,
+/// transactions, withdrawal windows) are kept in memory.
+pub struct Replay {
+    engine: Engine,
+    log: LogRows,
+    /// Rows read from the log so far and the index of their input.
+    rows: Vec<(usize, LogRow)>,
+    /// Timestamp of each row read, inherited by rows without timestamp.
+    clocks: Vec<u64>,
+    interval: usize,
+    mode: Mode,
+    /// Checkpoints and the number of rows applied when they were taken, in order.
+    checkpoints: Vec<(usize, Checkpoint)>,
+    /// Number of rows applied to the engine.
+    applied: usize,
+    /// Number of rows applied at least once, whose errors were recorded.
+    reported: usize,
+    errors: Vec<(usize, Errors)>,
+}
+
+impl Replay {
+    /// `engine` holds the state before the first row of the log, e.g. with its configuration.
+    pub fn new<I>(engine: Engine, log: I, interval: usize, mode: Mode) -> Self
+    where
+        I: IntoIterator<Item = (usize, LogRow)>,
+        I::IntoIter: 'static,
+    {
+        let checkpoints = vec![(0, engine.checkpoint())];
+        Replay {
+            engine,
+            log: Box::new(log.into_iter()),
+            rows: Vec::new(),
+            clocks: Vec::new(),
+            interval: interval.max(1),
+            mode,
+            checkpoints,
+            applied: 0,
+            reported: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Read the next row of the log, returning false once the log is exhausted.
+    fn read(&mut self) -> bool {
+        let Some((input, row)) = self.log.next() else {
+            return false;
+        };
+        let clock = self.clocks.last().copied().unwrap_or_default();
+        let timestamp = row.1.as_ref().ok().and_then(|row| row.timestamp);
+        self.clocks.push(timestamp.map_or(clock, |timestamp| clock.max(timestamp)));
+        self.rows.push((input, row));
+        true
+    }
+
+    /// Number of rows of the log included at `point`, `None` for an unknown tx.
+    /// The log is read up to the point.
+    pub fn position(&mut self, point: AsOf) -> Option<usize> {
+        match point {
+            AsOf::Line(line) => {
+                while self.rows.len() < line && self.read() {}
+                Some(line.min(self.rows.len()))
+            }
+            AsOf::Tx(tx) => {
+                let is_tx = |(_, (_, row)): &(usize, LogRow)| matches!(row, Ok(row) if row.tx == Some(tx));
+                if let Some(index) = self.rows.iter().position(is_tx) {
+                    return Some(index + 1);
+                }
+                while self.read() {
+                    if is_tx(self.rows.last().unwrap()) {
+                        return Some(self.rows.len());
+                    }
+                }
+                None
+            }
+            AsOf::Timestamp(timestamp) => {
+                while self.clocks.last().map_or(true, |clock| *clock <= timestamp) && self.read() {}
+                Some(self.clocks.partition_point(|clock| *clock <= timestamp))
+            }
+        }
+    }
+
+    /// Engine state at `point`.
+    ///
+    /// In strict mode the first error of the replayed rows is returned,
+    /// in lenient mode invalid rows are skipped.
+    pub fn as_of(&mut self, point: AsOf) -> Result<&Engine, Errors> {
+        let target = self.position(point).ok_or_else(|| Errors::InvalidInput(format!("no row for {}", point)))?;
+        if self.applied > target {
+            self.rewind(target);
+        }
+        while self.applied < target {
+            let (input, (line, row)) = &self.rows[self.applied];
+            let result = row.clone().and_then(|row| self.engine.apply(row, *line));
+            if let Err(error) = result {
+                if self.applied >= self.reported {
+                    self.errors.push((*input, error.clone()));
+                    self.reported = self.applied + 1;
+                }
+                if self.mode == Mode::Strict {
+                    // Leave the engine before the invalid row, so it is reported again
+                    self.rewind(self.applied);
+                    return Err(error);
+                }
+            }
+            self.applied += 1;
+            self.reported = self.reported.max(self.applied);
+            let taken = matches!(self.checkpoints.last(), Some((applied, _)) if *applied >= self.applied);
+            if self.applied % self.interval == 0 && !taken {
+                self.checkpoints.push((self.applied, self.engine.checkpoint()));
+            }
+        }
+        Ok(&self.engine)
+    }
+
+    /// Take the errors of the rows replayed since the last call, with the
+    /// index of their input: rows skipped in lenient mode and the row
+    /// stopping the replay in strict mode. Rows replayed again after going
+    /// back to a checkpoint are reported once.
+    pub fn drain_errors(&mut self) -> Vec<(usize, Errors)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Engine of the replay, at the point of the last query.
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+
+    /// Go back to the closest checkpoint taken before `target` rows were applied.
+    fn rewind(&mut self, target: usize) {
+        let (applied, checkpoint) = self.checkpoints.iter().rev().find(|(applied, _)| *applied <= target).unwrap();
+        self.engine.rewind(checkpoint);
+        self.applied = *applied;
+    }
+}
@@ -0,0 +1,117 @@
+use serde_derive::{Deserialize, Serialize};
+use crate::{Engine, Input, Operation};
+
+/// Decision of a rule on a transaction, with the reason for non allowed ones.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Decision {
+    Allow,
+    /// The transaction is skipped and reported as rejected.
+    Reject(String),
+    /// The transaction is not applied and kept for manual review,
+    /// see `Engine::take_held` and `Engine::apply_reviewed`.
+    Hold(String),
+}
+
+/// Action taken by a rule, as configured and reported in the audit entries.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    Reject,
+    Hold,
+}
+
+impl RuleAction {
+    fn decision(&self, reason: String) -> Decision {
+        match self {
+            RuleAction::Reject => Decision::Reject(reason),
+            RuleAction::Hold => Decision::Hold(reason),
+        }
+    }
+}
+
+/// Risk rule evaluated on every valid row before it is applied.
+///
+/// Rules have read access to the engine: accounts, transactions and
+/// per client statistics. A rejection by any rule wins over a hold.
+pub trait Rule: Send {
+    fn name(&self) -> &str;
+
+    fn evaluate(&self, row: &Input, engine: &Engine) -> Decision;
+}
+
+/// Disputes are not allowed once the client opened `max` disputes.
+pub struct MaxDisputes {
+    pub max: u32,
+    pub action: RuleAction,
+}
+
+impl Rule for MaxDisputes {
+    fn name(&self) -> &str {
+        "max_disputes"
+    }
+
+    fn evaluate(&self, row: &Input, engine: &Engine) -> Decision {
+        if row.op_type() != Some(Operation::Dispute) {
+            return Decision::Allow;
+        }
+        let disputes = engine.client_stats(row.client().unwrap()).disputes;
+        if disputes >= self.max {
+            return self.action.decision(format!("client already opened {} disputes", disputes));
+        }
+        Decision::Allow
+    }
+}
+
+/// Disputes are not allowed when they would bring the ratio of disputes per
+/// deposit of the client above `max_ratio`. Clients with less than
+/// `min_deposits` deposits are not evaluated.
+pub struct DisputeRatio {
+    pub max_ratio: f32,
+    pub min_deposits: u32,
+    pub action: RuleAction,
+}
+
+impl Rule for DisputeRatio {
+    fn name(&self) -> &str {
+        "dispute_ratio"
+    }
+
+    fn evaluate(&self, row: &Input, engine: &Engine) -> Decision {
+        if row.op_type() != Some(Operation::Dispute) {
+            return Decision::Allow;
+        }
+        let stats = engine.client_stats(row.client().unwrap());
+        if stats.deposits == 0 || stats.deposits < self.min_deposits {
+            return Decision::Allow;
+        }
+        let ratio = (stats.disputes + 1) as f32 / stats.deposits as f32;
+        if ratio > self.max_ratio {
+            return self.action.decision(format!("dispute ratio {:.2} above {}", ratio, self.max_ratio));
+        }
+        Decision::Allow
+    }
+}
+
+/// Built-in rules, as configured in the `rules` section of the configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RuleConfig {
+    MaxDisputes { max: u32, action: RuleAction },
+    DisputeRatio {
+        max_ratio: f32,
+        #[serde(default)]
+        min_deposits: u32,
+        action: RuleAction,
+    },
+}
+
+impl RuleConfig {
+    pub fn build(&self) -> Box<dyn Rule> {
+        match self {
+            RuleConfig::MaxDisputes { max, action } => Box::new(MaxDisputes { max: *max, action: *action }),
+            RuleConfig::DisputeRatio { max_ratio, min_deposits, action } => {
+                Box::new(DisputeRatio { max_ratio: *max_ratio, min_deposits: *min_deposits, action: *action })
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Summary of a run: rows by operation, accepted and rejected rows,
+//! amounts moved and processing rate, counted by `operate_account`.
+use std::{collections::{BTreeMap, HashMap}, fmt, time::Instant};
+use serde_derive::Serialize;
+use crate::{AccInfo, AccountKey, FundAccount, Funds, Operation};
+
+/// Outcome of a row applied by the engine.
+pub(crate) enum Outcome {
+    Accepted,
+    /// Applied without effect: dispute of an unknown tx, resolve of an
+    /// undisputed tx, dispute skipped on a locked account, ...
+    Ignored,
+    HeldForReview,
+    /// Rejection reason, error or `failed` for transactions recorded as failed.
+    Rejected(&'static str),
+}
+
+/// Counters of the rows applied by the engine since it was created.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Counters {
+    rows: u64,
+    operations: BTreeMap<&'static str,u64>,
+    accepted: u64,
+    ignored: u64,
+    held_for_review: u64,
+    rejected: BTreeMap<&'static str,u64>,
+    deposited: f32,
+    withdrawn: f32,
+    charged_back: f32,
+    /// Start of the first row and end of the last row.
+    started: Option<Instant>,
+    finished: Option<Instant>,
+}
+
+impl Counters {
+    pub(crate) fn start(&mut self) {
+        self.started.get_or_insert_with(Instant::now);
+    }
+
+    /// Count a row, `amount` is the amount it moved when accepted.
+    pub(crate) fn record(&mut self, operation: Option<Operation>, outcome: Outcome, amount: f32, charged_back: f32) {
+        self.rows += 1;
+        let name = match operation {
+            Some(Operation::Deposit) => "deposit",
+            Some(Operation::Withdrawal) => "withdrawal",
+            Some(Operation::Dispute) => "dispute",
+            Some(Operation::Resolve) => "resolve",
+            Some(Operation::Chargeback) => "chargeback",
+            Some(Operation::Transfer) => "transfer",
+            None => "invalid",
+        };
+        *self.operations.entry(name).or_default() += 1;
+        self.outcome(operation, outcome, amount, charged_back);
+    }
+
+    /// Count the outcome of a row held for review once it is applied,
+    /// the row itself being counted when it was held.
+    pub(crate) fn review(&mut self, operation: Option<Operation>, outcome: Outcome, amount: f32, charged_back: f32) {
+        self.held_for_review = self.held_for_review.saturating_sub(1);
+        self.outcome(operation, outcome, amount, charged_back);
+    }
+
+    fn outcome(&mut self, operation: Option<Operation>, outcome: Outcome, amount: f32, charged_back: f32) {
+        match outcome {
+            Outcome::Accepted => {
+                self.accepted += 1;
+                match operation {
+                    Some(Operation::Deposit) => self.deposited = Funds::get_amount(self.deposited + amount),
+                    Some(Operation::Withdrawal) => self.withdrawn = Funds::get_amount(self.withdrawn + amount),
+                    _ => {},
+                }
+            },
+            Outcome::Ignored => self.ignored += 1,
+            Outcome::HeldForReview => self.held_for_review += 1,
+            Outcome::Rejected(reason) => *self.rejected.entry(reason).or_default() += 1,
+        }
+        self.charged_back = Funds::get_amount(self.charged_back + charged_back);
+        self.finished = Some(Instant::now());
+    }
+
+    pub(crate) fn summary(&self, accounts: &HashMap<AccountKey,AccInfo>) -> Summary {
+        let elapsed_seconds = match (self.started, self.finished) {
+            (Some(started), Some(finished)) => finished.duration_since(started).as_secs_f64(),
+            _ => 0.0,
+        };
+        Summary {
+            rows: self.rows,
+            operations: self.operations.clone(),
+            accepted: self.accepted,
+            ignored: self.ignored,
+            held_for_review: self.held_for_review,
+            rejected: self.rejected.clone(),
+            deposited: self.deposited,
+            withdrawn: self.withdrawn,
+            held: Funds::get_amount(accounts.values().map(|acc| acc.held).sum()),
+            charged_back: self.charged_back,
+            locked_accounts: accounts.values().filter(|acc| acc.locked()).count(),
+            elapsed_seconds,
+            rows_per_second: if elapsed_seconds > 0.0 { self.rows as f64 / elapsed_seconds } else { 0.0 },
+        }
+    }
+}
+
+/// Summary of the rows applied by an engine.
+///
+/// Rows are counted once they reach the engine: rows the CSV reader can't
+/// parse are reported as input errors only. A row is rejected by a rule or
+/// limit (reason in snake case), by an error (`invalid_amount`,
+/// `conflict_transaction`, ...) or `failed` when the transaction is recorded
+/// but not applied (insufficient funds, locked account). Disputes, resolves
+/// and chargebacks leaving their transaction unchanged are ignored (unknown
+/// or undisputed tx, locked account), other rows are accepted. Rows held for
+/// review move to their outcome once applied with `Engine::apply_reviewed`.
+///
+/// Amounts are summed across assets. `held` and `locked_accounts` are the
+/// current balances, the other amounts are moved by the counted rows;
+/// `withdrawn` excludes fees.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub rows: u64,
+    pub operations: BTreeMap<&'static str,u64>,
+    pub accepted: u64,
+    pub ignored: u64,
+    pub held_for_review: u64,
+    pub rejected: BTreeMap<&'static str,u64>,
+    pub deposited: f32,
+    pub withdrawn: f32,
+    pub held: f32,
+    pub charged_back: f32,
+    pub locked_accounts: usize,
+    pub elapsed_seconds: f64,
+    pub rows_per_second: f64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Rows: {}", self.rows)?;
+        for (operation, rows) in &self.operations {
+            writeln!(f, "  {}: {}", operation, rows)?;
+        }
+        writeln!(f, "Accepted: {}", self.accepted)?;
+        writeln!(f, "Ignored: {}", self.ignored)?;
+        writeln!(f, "Held for review: {}", self.held_for_review)?;
+        writeln!(f, "Rejected: {}", self.rejected.values().sum::<u64>())?;
+        for (reason, rows) in &self.rejected {
+            writeln!(f, "  {}: {}", reason, rows)?;
+        }
+        writeln!(f, "Deposited: {}", self.deposited)?;
+        writeln!(f, "Withdrawn: {}", self.withdrawn)?;
+        writeln!(f, "Held: {}", self.held)?;
+        writeln!(f, "Charged back: {}", self.charged_back)?;
+        writeln!(f, "Locked accounts: {}", self.locked_accounts)?;
+        write!(f, "Processing rate: {:.0} rows/s ({:.3}s)", self.rows_per_second, self.elapsed_seconds)
+    }
+}
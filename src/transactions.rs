@@ -1,185 +1,529 @@
 use std::{collections::{HashMap, hash_map::Entry}};
-use crate::{Operation, error::Errors,AccInfo, Funds, FundAccount, Txs, Input};
+use crate::{Operation, error::Errors,AccInfo, AccountStatus, AccountKey, FreezeCause, Funds, FundAccount, Txs, TxStatus, Input, audit::{AuditEntry, RejectReason, Rejection}, engine::{Engine, HeldTransaction}, events::Event, rules::{Decision, RuleAction}, summary::Outcome};
 
-pub fn deposit(row: Input, accounts: &mut HashMap<u16,AccInfo>,transactions: &mut HashMap<u32,Txs>,line:i32)->Result<(), String> {
+/// Credit a fee to the house account and record it in the audit entries.
+///
+/// The house account is credited even when locked: locks stop the operations
+/// of a client, while fees are owed to the operator whatever its status.
+fn charge_fee(accounts: &mut HashMap<AccountKey,AccInfo>, audit: &mut Vec<AuditEntry>, events: &mut Vec<Event>, entry: AuditEntry) {
+    match &entry {
+        AuditEntry::Fee { line, house_account, asset, amount, .. } => {
+            let house = accounts.entry((*house_account, asset.clone())).or_insert_with(|| {
+                events.push(Event::AccountCreated { line: *line, client: *house_account, asset: asset.clone() });
+                AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active }
+            });
+            house.available = Funds::get_amount(house.available + amount);
+            house.total = Funds::get_amount(house.total + amount);
+        }
+        AuditEntry::Rule { .. } | AuditEntry::Lock { .. } => {}
+    }
+    audit.push(entry);
+}
+
+/// Fee of the operation according to the engine fee schedule, if any.
+fn fee_entry(engine: &Engine, row: &Input, operation: Operation, amount: f32, line: i32) -> Option<AuditEntry> {
+    let fees = engine.config.fees.as_ref()?;
+    let fee = fees.fee(row.client.unwrap(), operation, amount);
+    if fee <= 0.0 {
+        return None
+    }
+    Some(AuditEntry::Fee {
+        line,
+        tx: row.tx.unwrap(),
+        client: row.client.unwrap(),
+        asset: row.asset().to_string(),
+        operation,
+        amount: fee,
+        house_account: fees.house_account,
+    })
+}
+
+/// Lock the account once its client reaches one of the auto lock triggers,
+/// recording the reason on the account and in the audit entries.
+fn check_auto_lock(engine: &mut Engine, key: AccountKey, line: i32, tx: u32) {
+    let client = key.0;
+    let reason = match &engine.config.auto_lock {
+        Some(auto_lock) => auto_lock.trigger(&engine.client_stats(client)),
+        None => None,
+    };
+    match (reason, engine.accounts.get_mut(&key)) {
+        (Some(reason), Some(account)) if !account.locked() => {
+            account.status = AccountStatus::auto_locked(&reason, line, tx);
+            engine.events.push(Event::AccountLocked { client, asset: key.1.clone(), status: account.status.clone() });
+            engine.audit.push(AuditEntry::Lock { line, tx, client, asset: key.1, reason });
+        },
+        _ => {},
+    }
+}
+
+/// Open disputes of `tx` can be settled on an unlocked account, or on an account
+/// frozen by a partial chargeback of `tx`, so the rest of the disputed amount
+/// can still be resolved or charged back. Disputes are not opened on locked
+/// accounts, so open disputes of an auto locked account predate the lock and
+/// can be settled as well.
+fn can_settle(acc: &AccInfo, tx: u32) -> bool {
+    match &acc.status {
+        AccountStatus::Frozen { cause: FreezeCause::Chargeback, tx: Some(frozen), .. } => *frozen == tx,
+        AccountStatus::Frozen { cause: FreezeCause::AutoLock, .. } => true,
+        status => !status.is_locked(),
+    }
+}
+
+/// Count a row referencing a transaction of `owner` under another client ID,
+/// which may lock the account of the transaction.
+fn divergent_client(engine: &mut Engine, owner: u16, key: AccountKey, line: i32, tx: u32) -> Errors {
+    engine.stats.entry(owner).or_default().divergent_attempts += 1;
+    check_auto_lock(engine, key, line, tx);
+    Errors::SecurityErrDivergentClientId(line.to_string())
+}
+
+/// Transaction ID already recorded. Exact resends of the recorded row are
+/// idempotent and reported as duplicates, other rows are conflicts.
+fn reused_tx(recorded: &Txs, row: &Input, rejections: &mut Vec<Rejection>, line: i32) -> Result<(), Errors> {
+    if !recorded.info.is_duplicate_of(row) {
+        return Err(Errors::ConflictTransaction(line.to_string()))
+    }
+    rejections.push(Rejection { line, tx: row.tx.unwrap(), client: row.client.unwrap(), reason: RejectReason::DuplicateTransaction });
+    Ok(())
+}
+
+pub fn deposit(row: Input, engine: &mut Engine, line:i32)->Result<(), Errors> {
     let amount = Funds::get_amount(row.amount.unwrap());
-    let client = row.client;
-    match transactions.entry(row.tx.unwrap()) {
-        Entry::Occupied(_e) => {
-            return Err(Errors::ConflictTransaction(line.to_string()).to_string());
+    let key = row.account_key();
+    //Fees are taken from the deposited amount
+    let fee = fee_entry(engine, &row, Operation::Deposit, amount, line).map(|entry| entry.capped(amount));
+    let credit = Funds::get_amount(amount - fee.as_ref().map_or(0.0, AuditEntry::amount));
+    let client = row.client.unwrap();
+    let Engine { accounts, transactions, audit, rejections, stats, clock, sequence, events, .. } = engine;
+    let recorded = match transactions.entry(row.tx.unwrap()) {
+        Entry::Occupied(e) => {
+            return reused_tx(e.get(), &row, rejections, line);
         },
         Entry::Vacant(e) => {
             //New Transaction added
-            e.insert(Txs { info: row, in_dispute: false });
+            e.insert(Txs::new(row, *clock, *sequence))
         }
-    }
-    match accounts.entry(client.unwrap()) {
+    };
+    let applied = match accounts.entry(key) {
         Entry::Vacant(e) => {
             //No account record, creating new Account
-            e.insert(AccInfo { available: amount, held: 0.0, total: amount, locked: false });
+            events.push(Event::AccountCreated { line, client, asset: e.key().1.clone() });
+            e.insert(AccInfo { available: credit, held: 0.0, total: credit, status: AccountStatus::Active });
+            true
         },
         Entry::Occupied(mut e) => {
             //Found Account record: update
-            if !e.get_mut().locked{
-                e.get_mut().available = Funds::get_amount(e.get().available + amount);
-                e.get_mut().total = Funds::get_amount(e.get().total + amount);
+            if !e.get().locked(){
+                e.get_mut().available = Funds::get_amount(e.get().available + credit);
+                e.get_mut().total = Funds::get_amount(e.get().total + credit);
             }
+            !e.get().locked()
+        }
+    };
+    //Only the credited amount can be disputed, the fee stays with the house account
+    recorded.disputable = credit;
+    if !applied {
+        //Locked account: deposit recorded as failed
+        recorded.status = TxStatus::Failed;
+    }
+    if applied {
+        let stats = stats.entry(client).or_default();
+        stats.deposits += 1;
+        stats.deposited = Funds::get_amount(stats.deposited + amount);
+        if let Some(fee) = fee {
+            charge_fee(accounts, audit, events, fee);
         }
     }
     Ok(())
 }
 
-pub fn withdrawal(row: Input, accounts: &mut HashMap<u16,AccInfo>,transactions: &mut HashMap<u32,Txs>,line:i32)->Result<(), String> {
+pub fn withdrawal(row: Input, engine: &mut Engine, line:i32)->Result<(), Errors> {
     let amount = Funds::get_amount(row.amount.unwrap());
-    let client = row.client;
-        match transactions.entry(row.tx.unwrap()) {
-        Entry::Occupied(mut _e) => {
-            return Err(Errors::ConflictTransaction(line.to_string()).to_string());
+    let key = row.account_key();
+    //Fees are debited on top of the withdrawn amount
+    let fee = fee_entry(engine, &row, Operation::Withdrawal, amount, line);
+    let debit = Funds::get_amount(amount + fee.as_ref().map_or(0.0, AuditEntry::amount));
+    let (client, tx) = (row.client.unwrap(), row.tx.unwrap());
+    let Engine { accounts, transactions, audit, config, rejections, clock, sequence, withdrawals, stats, events, .. } = engine;
+    let recorded = match transactions.entry(tx) {
+        Entry::Occupied(e) => {
+            return reused_tx(e.get(), &row, rejections, line);
         },
         Entry::Vacant(e) => {
             //Create new TX
-            e.insert(Txs { info: row, in_dispute: false });
+            e.insert(Txs::new(row, *clock, *sequence))
         }
-    }
-    match accounts.entry(client.unwrap()) {
+    };
+    match accounts.entry(key) {
         Entry::Vacant(_e) => {
             //Account not found: withdrawal is not processed
+            recorded.status = TxStatus::Failed;
             return Ok(())
         },
         Entry::Occupied(mut e) => {
             //Update account record
-            if !e.get_mut().locked{
-                //Verify if account has funds/is locked
-                if e.get_mut().available >= amount{
-                    e.get_mut().available = Funds::get_amount(e.get().available - amount);
-                    e.get_mut().total = Funds::get_amount(e.get().total - amount);
+            //Verify if account has funds/is locked
+            if e.get().locked() || e.get().available < debit{
+                recorded.status = TxStatus::Failed;
+                return Ok(())
+            }
+            //Verify withdrawal limits, violations are rejected and reported
+            if let Some(limits) = &config.limits {
+                let limits = limits.for_client(client);
+                let history = withdrawals.entry(e.key().clone()).or_default();
+                if let Err(reason) = history.check(limits, *clock, amount) {
+                    recorded.status = TxStatus::Failed;
+                    rejections.push(Rejection { line, tx, client, reason });
+                    return Ok(())
                 }
+                history.record(limits, *clock, amount);
             }
+            e.get_mut().available = Funds::get_amount(e.get().available - debit);
+            e.get_mut().total = Funds::get_amount(e.get().total - debit);
         }
     }
+    stats.entry(client).or_default().withdrawals += 1;
+    if let Some(fee) = fee {
+        charge_fee(accounts, audit, events, fee);
+    }
     Ok(())
 }
 
-pub fn dispute(row: Input, accounts: &mut HashMap<u16,AccInfo>,transactions: &mut HashMap<u32,Txs>,line:i32)->Result<(), String> {
-    match transactions.entry(row.tx.unwrap()) {
+pub fn dispute(row: Input, engine: &mut Engine, line:i32)->Result<(), Errors> {
+    let (client, tx) = (row.client.unwrap(), row.tx.unwrap());
+    let Engine { accounts, transactions, stats, config, rejections, clock, sequence, events, .. } = engine;
+    let mut disputed = None;
+    let mut divergent = None;
+    match transactions.entry(tx) {
         Entry::Occupied(mut e) => {
             if e.get().info.client == row.client{
                 //Check if clientId and tx in row match clientId and tx at HashMap 
-                if !accounts.get(&row.client.unwrap()).unwrap().locked{
+                //Disputes apply to the asset of the original transaction,
+                //holding funds on the recipient account for transfers
+                let key = e.get().info.disputed_account_key();
+                //Transactions that were never applied cannot be disputed
+                if e.get().status == TxStatus::Failed {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::FailedTransaction });
+                    return Ok(())
+                }
+                //Late disputes are rejected and reported
+                let window = config.dispute_windows.as_ref().and_then(|w| w.for_operation(e.get().info.op_type.unwrap()));
+                if window.is_some_and(|w| w.expired(*clock - e.get().timestamp, *sequence - e.get().sequence)) {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::DisputeWindowExpired });
+                    return Ok(())
+                }
+                //Partial disputes hold the given amount, the remaining disputable amount otherwise
+                let amount = row.amount.map_or(e.get().disputable, Funds::get_amount);
+                if amount > e.get().disputable {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::DisputableAmountExceeded });
+                    return Ok(())
+                }
+                if amount > 0.0 && accounts.get(&key).is_some_and(|acc| !acc.locked()){
                     //Update account: under dispute
-                    e.get_mut().in_dispute = true;
-                    let new_account_value = accounts.get_mut(&row.client.unwrap()).unwrap();
-                    new_account_value.available = Funds::get_amount(new_account_value.available - e.get().info.amount.unwrap());
-                    new_account_value.held = Funds::get_amount(new_account_value.held + e.get().info.amount.unwrap());
+                    let opened = !e.get().in_dispute();
+                    let txs = e.get_mut();
+                    txs.disputed = Funds::get_amount(txs.disputed + amount);
+                    txs.disputable = Funds::get_amount(txs.disputable - amount);
+                    let new_account_value = accounts.get_mut(&key).unwrap();
+                    new_account_value.available = Funds::get_amount(new_account_value.available - amount);
+                    new_account_value.held = Funds::get_amount(new_account_value.held + amount);
+                    let stats = stats.entry(client).or_default();
+                    stats.disputes += 1;
+                    if opened {
+                        stats.open_disputes += 1;
+                    }
+                    stats.disputed = Funds::get_amount(stats.disputed + amount);
+                    events.push(Event::DisputeOpened { line, tx, client, asset: key.1.clone(), amount });
+                    disputed = Some(key);
                 }
             }else{
-                return Err(Errors::SecurityErrDivergentClientId(line.to_string()).to_string())
+                divergent = Some((e.get().info.client.unwrap(), e.get().info.account_key()));
             }
         },
         Entry::Vacant(_) => {
             //TX not found
         },
     }
+    if let Some((owner, key)) = divergent {
+        return Err(divergent_client(engine, owner, key, line, tx))
+    }
+    if let Some((_, asset)) = disputed {
+        //Triggers are evaluated on the disputer stats, so they freeze the disputer
+        //account even when a transfer holds the funds on the recipient account
+        check_auto_lock(engine, (client, asset), line, tx);
+    }
     Ok(())
 }
 
-pub fn resolve(row: Input, accounts: &mut HashMap<u16,AccInfo>,transactions: &mut HashMap<u32,Txs>,line:i32)->Result<(), String> {
-    match transactions.entry(row.tx.unwrap()) {
+pub fn resolve(row: Input, engine: &mut Engine, line:i32)->Result<(), Errors> {
+    let (client, tx) = (row.client.unwrap(), row.tx.unwrap());
+    let Engine { accounts, transactions, stats, rejections, .. } = engine;
+    let mut divergent = None;
+    match transactions.entry(tx) {
         Entry::Occupied(mut e) => {
-            match e.get().in_dispute{
-                true => {
-                    if e.get().info.client == row.client {
-                        if !accounts.get(&row.client.unwrap()).unwrap().locked{
-                            //Check if clientId and tx in row match clientId and tx at HashMap 
-                            e.get_mut().in_dispute = false;
-                            //Update: not under dispute anymore
-                            let new_account_value = accounts.get_mut(&row.client.unwrap()).unwrap();
-                            new_account_value.available = Funds::get_amount(new_account_value.available + e.get().info.amount.unwrap());
-                            new_account_value.held = Funds::get_amount(new_account_value.held - e.get().info.amount.unwrap());
-                        }
-                    }else{
-                        return Err(Errors::SecurityErrDivergentClientId(line.to_string()).to_string())
-                    }
+            if e.get().info.client == row.client {
+                //Transactions that were never applied cannot be disputed
+                if e.get().status == TxStatus::Failed {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::FailedTransaction });
+                    return Ok(())
                 }
-                false => {
+                if !e.get().in_dispute() {
                     //TX not previously under dispute, skip
-                },
+                    return Ok(())
+                }
+                let key = e.get().info.disputed_account_key();
+                //Partial resolves release the given amount, the whole disputed amount otherwise
+                let amount = row.amount.map_or(e.get().disputed, Funds::get_amount);
+                if amount > e.get().disputed {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::DisputedAmountExceeded });
+                    return Ok(())
+                }
+                if accounts.get(&key).is_some_and(|acc| can_settle(acc, tx)){
+                    //Check if clientId and tx in row match clientId and tx at HashMap 
+                    //Update: released amount is not under dispute anymore and can be disputed again
+                    let txs = e.get_mut();
+                    txs.disputed = Funds::get_amount(txs.disputed - amount);
+                    txs.disputable = Funds::get_amount(txs.disputable + amount);
+                    let new_account_value = accounts.get_mut(&key).unwrap();
+                    new_account_value.available = Funds::get_amount(new_account_value.available + amount);
+                    new_account_value.held = Funds::get_amount(new_account_value.held - amount);
+                    if !e.get().in_dispute() {
+                        let stats = stats.entry(client).or_default();
+                        stats.open_disputes = stats.open_disputes.saturating_sub(1);
+                    }
+                }
+            }else{
+                divergent = Some((e.get().info.client.unwrap(), e.get().info.account_key()));
             }
         },
         Entry::Vacant(_) => {
             //TX not found
         },
     }
+    if let Some((owner, key)) = divergent {
+        return Err(divergent_client(engine, owner, key, line, tx))
+    }
     Ok(())
 }
 
-pub fn chargeback(row: Input, accounts: &mut HashMap<u16,AccInfo>,transactions: &mut HashMap<u32,Txs>,line:i32)->Result<(), String> {
-    match transactions.entry(row.tx.unwrap()) {
+pub fn chargeback(row: Input, engine: &mut Engine, line:i32)->Result<(), Errors> {
+    let (client, tx) = (row.client.unwrap(), row.tx.unwrap());
+    let Engine { accounts, transactions, stats, rejections, events, .. } = engine;
+    let mut divergent = None;
+    match transactions.entry(tx) {
         Entry::Occupied(mut e) => {
-            match e.get().in_dispute{
-                true => {
-                    if e.get().info.client == row.client {
-                        if !accounts.get(&row.client.unwrap()).unwrap().locked{
-                        //Check if clientId and tx in row match clientId and tx at HashMap 
-                            e.get_mut().in_dispute = false;
-                            let new_account_value = accounts.get_mut(&row.client.unwrap()).unwrap();
-                            new_account_value.held = Funds::get_amount(new_account_value.held - e.get().info.amount.unwrap());
-                            new_account_value.total = Funds::get_amount(new_account_value.total - e.get().info.amount.unwrap());
-                            new_account_value.locked = true;
+            if e.get().info.client == row.client {
+                //Transactions that were never applied cannot be disputed
+                if e.get().status == TxStatus::Failed {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::FailedTransaction });
+                    return Ok(())
+                }
+                if !e.get().in_dispute() {
+                    //TX not previously under dispute, skip
+                    return Ok(())
+                }
+                let key = e.get().info.disputed_account_key();
+                //Partial chargebacks reverse the given amount, the whole disputed amount otherwise
+                let amount = row.amount.map_or(e.get().disputed, Funds::get_amount);
+                if amount > e.get().disputed {
+                    rejections.push(Rejection { line, tx, client, reason: RejectReason::DisputedAmountExceeded });
+                    return Ok(())
+                }
+                if accounts.get(&key).is_some_and(|acc| can_settle(acc, tx)){
+                //Check if clientId and tx in row match clientId and tx at HashMap 
+                    let txs = e.get_mut();
+                    txs.disputed = Funds::get_amount(txs.disputed - amount);
+                    let new_account_value = accounts.get_mut(&key).unwrap();
+                    new_account_value.held = Funds::get_amount(new_account_value.held - amount);
+                    new_account_value.total = Funds::get_amount(new_account_value.total - amount);
+                    events.push(Event::ChargebackApplied { line, tx, client, asset: key.1.clone(), amount });
+                    //Later chargebacks of a partially charged back tx keep the first freeze
+                    if !new_account_value.locked() {
+                        new_account_value.status = AccountStatus::charged_back(line, tx);
+                        events.push(Event::AccountLocked { client: key.0, asset: key.1.clone(), status: new_account_value.status.clone() });
+                    }
+                    let stats = stats.entry(client).or_default();
+                    stats.chargebacks += 1;
+                    if !e.get().in_dispute() {
+                        stats.open_disputes = stats.open_disputes.saturating_sub(1);
+                    }
+                    if e.get().info.op_type == Some(Operation::Transfer){
+                        //Reversed transfer: funds go back to the sender
+                        if let Some(sender) = accounts.get_mut(&e.get().info.account_key()){
+                            sender.available = Funds::get_amount(sender.available + amount);
+                            sender.total = Funds::get_amount(sender.total + amount);
                         }
-                    }else{
-                        return Err(Errors::SecurityErrDivergentClientId(line.to_string()).to_string())
                     }
                 }
-                false => {
-                    //TX not previously under dispute, skip
-                },
+            }else{
+                divergent = Some((e.get().info.client.unwrap(), e.get().info.account_key()));
             }
         },
         Entry::Vacant(_) => {
             //TX not found
         },
     }
+    if let Some((owner, key)) = divergent {
+        return Err(divergent_client(engine, owner, key, line, tx))
+    }
+    Ok(())
+}
+
+pub fn transfer(row: Input, engine: &mut Engine, line:i32)->Result<(), Errors> {
+    let Engine { accounts, transactions, rejections, clock, sequence, events, .. } = engine;
+    let amount = Funds::get_amount(row.amount.unwrap());
+    let from = row.account_key();
+    let to = row.recipient_key();
+    let recorded = match transactions.entry(row.tx.unwrap()) {
+        Entry::Occupied(e) => {
+            return reused_tx(e.get(), &row, rejections, line);
+        },
+        Entry::Vacant(e) => {
+            //Create new TX
+            e.insert(Txs::new(row, *clock, *sequence))
+        }
+    };
+    //Sender should have funds, both accounts should not be locked
+    let has_funds = accounts.get(&from).is_some_and(|acc| !acc.locked() && acc.available >= amount);
+    let recipient_unlocked = !matches!(accounts.get(&to), Some(acc) if acc.locked());
+    if !has_funds || !recipient_unlocked {
+        recorded.status = TxStatus::Failed;
+        return Ok(())
+    }
+    let sender = accounts.get_mut(&from).unwrap();
+    sender.available = Funds::get_amount(sender.available - amount);
+    sender.total = Funds::get_amount(sender.total - amount);
+    //Recipient without account record: creating new Account
+    let recipient = accounts.entry(to).or_insert_with_key(|(client, asset)| {
+        events.push(Event::AccountCreated { line, client: *client, asset: asset.clone() });
+        AccInfo { available: 0.0, held: 0.0, total: 0.0, status: AccountStatus::Active }
+    });
+    recipient.available = Funds::get_amount(recipient.available + amount);
+    recipient.total = Funds::get_amount(recipient.total + amount);
     Ok(())
 }
 
-pub fn operate_account(row: Input, accs: &mut HashMap<u16,AccInfo>, line: i32,txs: &mut HashMap<u32,Txs>)  -> Result<(), String> {
+/// Evaluate the engine rules on a validated row. Returns the first rejection,
+/// or the first hold when no rule rejects the row.
+fn evaluate_rules(row: &Input, engine: &Engine) -> Option<(String, RuleAction, String)> {
+    let mut held = None;
+    for rule in &engine.rules {
+        match rule.evaluate(row, engine) {
+            Decision::Allow => {},
+            Decision::Reject(reason) => return Some((rule.name().to_string(), RuleAction::Reject, reason)),
+            Decision::Hold(reason) => {
+                if held.is_none() {
+                    held = Some((rule.name().to_string(), RuleAction::Hold, reason));
+                }
+            },
+        }
+    }
+    held
+}
+
+/// Apply a validated row to the engine.
+pub(crate) fn dispatch(row: Input, engine: &mut Engine, line: i32) -> Result<(), Errors> {
+    match row.op_type.unwrap() {
+        Operation::Deposit => deposit(row, engine, line),
+        Operation::Withdrawal => withdrawal(row, engine, line),
+        Operation::Dispute => dispute(row, engine, line),
+        Operation::Resolve => resolve(row, engine, line),
+        Operation::Chargeback => chargeback(row, engine, line),
+        Operation::Transfer => transfer(row, engine, line),
+    }
+}
+
+/// Apply a row to the engine, counting it in the run summary.
+pub fn operate_account(row: Input, engine: &mut Engine, line: i32)  -> Result<(), Errors> {
+    engine.counters.start();
+    let (operation, outcome, amount, charged_back, result) = counted(row, engine, line, validate_and_apply);
+    engine.counters.record(operation, outcome, amount, charged_back);
+    result
+}
+
+/// Apply a row held for review, counting its outcome in the run summary.
+pub(crate) fn apply_reviewed(row: Input, engine: &mut Engine, line: i32) -> Result<(), Errors> {
+    let (operation, outcome, amount, charged_back, result) = counted(row, engine, line, dispatch);
+    engine.counters.review(operation, outcome, amount, charged_back);
+    result
+}
+
+type Counted = (Option<Operation>, Outcome, f32, f32, Result<(), Errors>);
+
+/// Apply a row with `apply` and find its outcome, the amount it moved
+/// and the amount it charged back, from the engine changes.
+fn counted(row: Input, engine: &mut Engine, line: i32, apply: fn(Input, &mut Engine, i32) -> Result<(), Errors>) -> Counted {
+    let (operation, tx) = (row.op_type, row.tx);
+    let amount = row.amount.map_or(0.0, Funds::get_amount);
+    let recorded = |engine: &Engine| tx.and_then(|tx| engine.transactions.get(&tx)).map(|txs| (txs.status, txs.disputed, txs.disputable));
+    let before = recorded(engine);
+    let (rejections, held, events) = (engine.rejections.len(), engine.held.len(), engine.events.len());
+    let result = apply(row, engine, line);
+    let after = recorded(engine);
+    let outcome = match &result {
+        Err(error) => Outcome::Rejected(error.name()),
+        Ok(()) if engine.rejections.len() > rejections => Outcome::Rejected(engine.rejections.last().unwrap().reason.name()),
+        Ok(()) if engine.held.len() > held => Outcome::HeldForReview,
+        //New transaction recorded but not applied: insufficient funds, locked account
+        Ok(()) if before.is_none() && matches!(after, Some((TxStatus::Failed, _, _))) => Outcome::Rejected("failed"),
+        //Dispute, resolve or chargeback leaving the transaction unchanged
+        Ok(()) if matches!(operation, Some(Operation::Dispute | Operation::Resolve | Operation::Chargeback)) && before == after => Outcome::Ignored,
+        Ok(()) => Outcome::Accepted,
+    };
+    let charged_back = engine.events[events..].iter().map(|event| match event {
+        Event::ChargebackApplied { amount, .. } => *amount,
+        _ => 0.0,
+    }).sum();
+    (operation, outcome, amount, charged_back, result)
+}
+
+fn validate_and_apply(row: Input, engine: &mut Engine, line: i32) -> Result<(), Errors> {
     let op_type = match row.op_type{
         Some(op) => op,
-        None => return Err(Errors::InvalidOperation(line.to_string()).to_string()),
+        None => return Err(Errors::InvalidOperation(line.to_string())),
     };
     match row.client{
         Some(_) => {},
-        None => return Err(Errors::InvalidClient(line.to_string()).to_string()),
+        None => return Err(Errors::InvalidClient(line.to_string())),
     };
     match row.tx{
         Some(_) => {},
-        None => return Err(Errors::InvalidTx(line.to_string()).to_string()),
+        None => return Err(Errors::InvalidTx(line.to_string())),
     };
     match op_type {
-        Operation::Deposit=> {
+        Operation::Deposit | Operation::Withdrawal => {
             match row.amount{
                 Some(amount) => amount,
-                None => return Err(Errors::InvalidAmount(line.to_string()).to_string()),
+                None => return Err(Errors::InvalidAmount(line.to_string())),
             };
-            return deposit(row, accs,txs,line);
         },
-        Operation::Withdrawal=> {
+        Operation::Transfer => {
+            //Negative transfers would debit the recipient without checking its funds
             match row.amount{
-                Some(amount) => amount,
-                None => return Err(Errors::InvalidAmount(line.to_string()).to_string()),
+                Some(amount) if amount > 0.0 => amount,
+                _ => return Err(Errors::InvalidAmount(line.to_string())),
+            };
+            match row.to{
+                Some(to) if row.client != Some(to) => {},
+                _ => return Err(Errors::InvalidRecipient(line.to_string())),
             };
-            return withdrawal(row,accs,txs,line)
-        },
-        Operation::Dispute=> {
-            dispute(row,accs,txs,line)
         },
-        Operation::Resolve => {
-            resolve(row,accs,txs,line)
+        Operation::Dispute | Operation::Resolve | Operation::Chargeback => {
+            //Optional amount of partial disputes
+            if row.amount.is_some_and(|amount| amount <= 0.0) {
+                return Err(Errors::InvalidAmount(line.to_string()));
+            }
         },
-        Operation::Chargeback => {
-            chargeback(row,accs,txs,line)
+    }
+    //Risk rules are evaluated before any balance change
+    if let Some((rule, action, reason)) = evaluate_rules(&row, engine) {
+        let (client, tx) = (row.client.unwrap(), row.tx.unwrap());
+        engine.audit.push(AuditEntry::Rule { line, tx, client, rule: rule.clone(), action, reason: reason.clone() });
+        match action {
+            RuleAction::Reject => engine.rejections.push(Rejection { line, tx, client, reason: RejectReason::RuleRejected }),
+            RuleAction::Hold => engine.held.push(HeldTransaction { line, rule, reason, row }),
         }
+        return Ok(())
     }
-    
+    dispatch(row, engine, line)
 }
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use crate::{audit::RejectReason, config::WithdrawalLimits, engine::WithdrawalSnapshot, AccountKey, FundAccount, Funds};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Withdrawals applied to an account, used to enforce withdrawal limits.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct WithdrawalHistory {
+    day: u64,
+    day_amount: f32,
+    window: VecDeque<(u64, f32)>,
+}
+
+impl WithdrawalHistory {
+    pub(crate) fn snapshot(&self, key: &AccountKey) -> WithdrawalSnapshot {
+        WithdrawalSnapshot {
+            client: key.0,
+            asset: key.1.clone(),
+            day: self.day,
+            day_amount: self.day_amount,
+            window: self.window.iter().copied().collect(),
+        }
+    }
+
+    /// History of a snapshot and the account it belongs to.
+    pub(crate) fn restore(snapshot: WithdrawalSnapshot) -> (AccountKey, Self) {
+        let history = WithdrawalHistory { day: snapshot.day, day_amount: snapshot.day_amount, window: snapshot.window.into() };
+        ((snapshot.client, snapshot.asset), history)
+    }
+
+    /// Drop the withdrawals outside of the window ending at `now`.
+    fn expire(&mut self, limits: &WithdrawalLimits, now: u64) {
+        if now / SECONDS_PER_DAY != self.day {
+            self.day = now / SECONDS_PER_DAY;
+            self.day_amount = 0.0;
+        }
+        while let Some((timestamp, _)) = self.window.front() {
+            //Windows ending past u64::MAX end at u64::MAX
+            if timestamp.saturating_add(limits.window_seconds) > now {
+                break;
+            }
+            self.window.pop_front();
+        }
+    }
+
+    /// Check a withdrawal of `amount` at `now` against `limits`.
+    pub(crate) fn check(&mut self, limits: &WithdrawalLimits, now: u64, amount: f32) -> Result<(), RejectReason> {
+        self.expire(limits, now);
+        if limits.max_amount.is_some_and(|max| amount > max) {
+            return Err(RejectReason::WithdrawalAboveMax);
+        }
+        if limits.daily_amount.is_some_and(|max| Funds::get_amount(self.day_amount + amount) > max) {
+            return Err(RejectReason::DailyLimitExceeded);
+        }
+        if limits.window_count.is_some_and(|max| self.window.len() + 1 > max) {
+            return Err(RejectReason::WindowCountExceeded);
+        }
+        let window_amount: f32 = self.window.iter().map(|(_, amount)| amount).sum();
+        if limits.window_amount.is_some_and(|max| Funds::get_amount(window_amount + amount) > max) {
+            return Err(RejectReason::WindowAmountExceeded);
+        }
+        Ok(())
+    }
+
+    /// Record an applied withdrawal, previously checked at `now`.
+    pub(crate) fn record(&mut self, limits: &WithdrawalLimits, now: u64, amount: f32) {
+        self.day_amount = Funds::get_amount(self.day_amount + amount);
+        if limits.window_seconds > 0 {
+            self.window.push_back((now, amount));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::{self, Write};
+
+/// Parameters of a synthetic transaction workload.
+///
+/// Ratios are probabilities in the `0.0..=1.0` range:
+/// - `dispute_ratio`: chance of a row being a dispute on a previous deposit.
+///   Open disputes are settled (resolve or chargeback) at the same rate.
+/// - `chargeback_ratio`: share of settled disputes ending in a chargeback,
+///   the remaining ones are resolved.
+/// - `malformed_ratio`: chance of a row carrying an invalid column.
+#[derive(Debug, Clone)]
+pub struct WorkloadConfig {
+    pub clients: u16,
+    pub transactions: u64,
+    pub dispute_ratio: f64,
+    pub chargeback_ratio: f64,
+    pub malformed_ratio: f64,
+    pub seed: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        WorkloadConfig {
+            clients: 1000,
+            transactions: 100_000,
+            dispute_ratio: 0.01,
+            chargeback_ratio: 0.2,
+            malformed_ratio: 0.0,
+            seed: 42,
+        }
+    }
+}
+
+/// Amounts are generated as integer units of 0.0001, matching
+/// the 4 decimal places precision accepted by the engine.
+const UNITS: u64 = 10_000;
+
+/// Number of recent deposits kept per client as dispute candidates.
+/// Keeps generator memory flat regardless of the workload size.
+const RECENT_DEPOSITS: usize = 8;
+
+/// Small SplitMix64 generator.
+///
+/// Avoids importing the rand crate, while keeping workloads
+/// reproducible for a given seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform value in `0..bound`. `bound` must be greater than 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+#[derive(Default, Clone)]
+struct ClientState {
+    balance: u64,
+    deposits: Vec<(u32, u64)>,
+}
+
+fn fmt_units(units: u64) -> String {
+    format!("{}.{:04}", units / UNITS, units % UNITS)
+}
+
+/// Write a CSV workload described by `config` into `out`.
+///
+/// Rows follow the chronological semantics of the engine: disputes only
+/// reference previous deposits of the same client and resolves/chargebacks
+/// only reference open disputes. Returns the number of rows written.
+pub fn generate<W: Write>(config: &WorkloadConfig, out: W) -> io::Result<u64> {
+    let mut out = io::BufWriter::new(out);
+    let mut rng = Rng::new(config.seed);
+    let clients = config.clients.max(1);
+    let mut states = vec![ClientState::default(); clients as usize];
+    let mut open_disputes: Vec<(u16, u32)> = Vec::new();
+    let mut next_tx: u32 = 1;
+
+    writeln!(out, "type, client, tx, amount")?;
+    for _ in 0..config.transactions {
+        let client = rng.below(clients as u64) as u16;
+        let id = client + 1;
+
+        if rng.next_f64() < config.malformed_ratio {
+            let tx = next_tx;
+            next_tx += 1;
+            match rng.below(4) {
+                0 => writeln!(out, "deposits, {}, {}, 1.0", id, tx)?,
+                1 => writeln!(out, "deposit, client_{}, {}, 1.0", id, tx)?,
+                2 => writeln!(out, "deposit, {}, tx_{}, 1.0", id, tx)?,
+                _ => writeln!(out, "deposit, {}, {}, 1.0.0", id, tx)?,
+            }
+            continue;
+        }
+
+        if !open_disputes.is_empty() && rng.next_f64() < config.dispute_ratio {
+            let index = rng.below(open_disputes.len() as u64) as usize;
+            let (owner, tx) = open_disputes.swap_remove(index);
+            let op = if rng.next_f64() < config.chargeback_ratio {
+                "chargeback"
+            } else {
+                "resolve"
+            };
+            writeln!(out, "{}, {}, {},", op, owner, tx)?;
+            continue;
+        }
+
+        let state = &mut states[client as usize];
+        if !state.deposits.is_empty() && rng.next_f64() < config.dispute_ratio {
+            let index = rng.below(state.deposits.len() as u64) as usize;
+            let (tx, amount) = state.deposits.swap_remove(index);
+            state.balance = state.balance.saturating_sub(amount);
+            open_disputes.push((id, tx));
+            writeln!(out, "dispute, {}, {},", id, tx)?;
+            continue;
+        }
+
+        let tx = next_tx;
+        next_tx += 1;
+        // Roughly 60% deposits, 40% withdrawals. Around 10% of the
+        // withdrawals exceed the client balance and should be skipped.
+        if state.balance == 0 || rng.below(10) < 6 {
+            let amount = 1 + rng.below(1000 * UNITS);
+            state.balance += amount;
+            if state.deposits.len() == RECENT_DEPOSITS {
+                state.deposits.remove(0);
+            }
+            state.deposits.push((tx, amount));
+            writeln!(out, "deposit, {}, {}, {}", id, tx, fmt_units(amount))?;
+        } else {
+            let amount = if rng.below(10) == 0 {
+                state.balance + 1 + rng.below(100 * UNITS)
+            } else {
+                1 + rng.below(state.balance)
+            };
+            if amount <= state.balance {
+                state.balance -= amount;
+            }
+            writeln!(out, "withdrawal, {}, {}, {}", id, tx, fmt_units(amount))?;
+        }
+    }
+    out.flush()?;
+    Ok(config.transactions)
+}
@@ -0,0 +1,85 @@
+//! Embedding the core engine through its public API only, without the CSV
+//! and JSON features: `cargo test --no-default-features --test core_engine`.
+use rust_coding_test::{Engine, Input, Operation, DEFAULT_ASSET};
+
+/// Rows built with the `Input` constructors are applied by the engine.
+///
+/// Input:
+///
+/// `type, client, tx, amount, asset, to`
+///
+/// `deposit, 1, 1, 2.0, , `
+///
+/// `dispute, 1, 1, , , `
+///
+/// `deposit, 1, 2, 5.0, btc, `
+///
+/// `transfer, 1, 3, 1.5, btc, 2`
+///
+/// `withdrawal, 2, 4, 0.5, btc, `
+///
+/// Expected:
+///
+/// `client, asset, available, held, total, locked`
+///
+/// `1, default, 0.0, 2.0, 2.0, false`
+///
+/// `1, btc, 3.5, 0.0, 3.5, false`
+///
+/// `2, btc, 1.0, 0.0, 1.0, false`
+#[test]
+fn core_engine() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.apply(Input::deposit(1, 1, 2.0), 1), Ok(()));
+    assert_eq!(engine.apply(Input::dispute(1, 1), 2), Ok(()));
+    assert_eq!(engine.apply(Input::deposit(1, 2, 5.0).with_asset("btc"), 3), Ok(()));
+    assert_eq!(engine.apply(Input::transfer(1, 3, 1.5, 2).with_asset("btc"), 4), Ok(()));
+    assert_eq!(engine.apply(Input::withdrawal(2, 4, 0.5).with_asset("btc"), 5), Ok(()));
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available(), acc.held(), acc.total(), acc.locked()), (0.0, 2.0, 2.0, false));
+    let acc = engine.account(1, "btc").unwrap();
+    assert_eq!((acc.available(), acc.held(), acc.total()), (3.5, 0.0, 3.5));
+    let acc = engine.account(2, "btc").unwrap();
+    assert_eq!((acc.available(), acc.held(), acc.total()), (1.0, 0.0, 1.0));
+
+    let transfer = engine.transaction(3).unwrap().input();
+    assert_eq!((transfer.op_type(), transfer.client(), transfer.to(), transfer.asset()), (Some(Operation::Transfer), Some(1), Some(2), "btc"));
+}
+
+/// Partial disputes, resolves and chargebacks take their amount from `with_amount`.
+///
+/// Input:
+///
+/// `type, client, tx, amount`
+///
+/// `deposit, 1, 1, 10.0`
+///
+/// `dispute, 1, 1, 4.0`
+///
+/// `resolve, 1, 1, 1.0`
+///
+/// `chargeback, 1, 1, `
+///
+/// Expected:
+///
+/// `client, available, held, total, locked`
+///
+/// `1, 7.0, 0.0, 7.0, true`
+#[test]
+fn partial_dispute_rows() {
+    let mut engine = Engine::new();
+    let rows = [
+        Input::deposit(1, 1, 10.0).with_timestamp(10),
+        Input::dispute(1, 1).with_amount(4.0),
+        Input::resolve(1, 1).with_amount(1.0),
+        Input::chargeback(1, 1),
+    ];
+    for (line, row) in rows.into_iter().enumerate() {
+        assert_eq!(engine.apply(row, line as i32 + 1), Ok(()));
+    }
+
+    let acc = engine.account(1, DEFAULT_ASSET).unwrap();
+    assert_eq!((acc.available(), acc.held(), acc.total(), acc.locked()), (7.0, 0.0, 7.0, true));
+    assert_eq!(engine.transaction(1).unwrap().input().timestamp(), Some(10));
+}